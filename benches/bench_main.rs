@@ -1,9 +1,15 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use std::hint::black_box;
-use zenith_lib::{gameboy::gameboy::EmulatorConfig, ppu::ppu, run_emulator};
+use zenith_lib::{
+    apu::{apu::AudioConfig, blip::AudioQuality, highpass::HighPassFilter, ring_buffer::AudioRing},
+    gameboy::gameboy::EmulatorConfig,
+    ppu::ppu,
+    run_emulator,
+    serial::peripheral::SerialPeripheral,
+};
 
 pub fn run_bench(rom_path: &str, num_cycles: u64) {
-    let (sound_send, sound_recv) = std::sync::mpsc::sync_channel::<Vec<i16>>(1);
+    let sound_ring = std::sync::Arc::new(AudioRing::new(4096 * 2, 2));
     let (frame_send, frame_recv) = std::sync::mpsc::sync_channel::<ppu::FrameBuffer>(1);
 
     let emu_ctx = run_emulator(
@@ -11,9 +17,24 @@ pub fn run_bench(rom_path: &str, num_cycles: u64) {
         EmulatorConfig {
             bp_chan: None,
             comp_mode: None,
-            sound_chan: Some(sound_send),
+            model: None,
+            boot_rom_dir: None,
+            dmg_palette: None,
+            frame_blending: ppu::FrameBlending::Off,
+            audio: AudioConfig::new(
+                44_100,
+                4096,
+                2,
+                AudioQuality::Medium,
+                HighPassFilter::Accurate,
+            ),
+            sound_chan: Some(sound_ring),
             frame_chan: Some(frame_send),
+            sgb_frame_chan: None,
+            viewer_chan: None,
+            serial: SerialPeripheral::None,
             input_recv: None,
+            command_recv: None,
             max_cycles: Some(num_cycles),
             enable_saving: false,
             sync_audio: false,
@@ -21,15 +42,6 @@ pub fn run_bench(rom_path: &str, num_cycles: u64) {
         },
     );
 
-    let a_thread = std::thread::spawn(move || loop {
-        match sound_recv.recv() {
-            Ok(val) => {
-                black_box(val);
-            }
-            Err(_err) => break,
-        }
-    });
-
     loop {
         match frame_recv.recv() {
             Ok(val) => {
@@ -40,7 +52,6 @@ pub fn run_bench(rom_path: &str, num_cycles: u64) {
     }
 
     emu_ctx.handle.join().unwrap();
-    a_thread.join().unwrap();
 }
 
 fn cpu_instrs_bench() {
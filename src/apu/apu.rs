@@ -1,26 +1,83 @@
-use std::sync::mpsc::SyncSender;
-
-use crate::{GbCtx, GB_DEFAULT_FPS, TARGET_FPS};
+use crate::{soc::hw_reg::*, GbCtx, GB_DEFAULT_FPS, TARGET_FPS};
 
 use super::{
-    audiocvt::AudioCVT, channel1::Channel1, channel2::Channel2, channel3::Channel3,
-    channel4::Channel4, wav_file::write_wav, Channel,
+    blip::{AudioQuality, BlipBuffer},
+    channel1::Channel1,
+    channel2::Channel2,
+    channel3::Channel3,
+    channel4::Channel4,
+    highpass::{HighPass, HighPassFilter},
+    ring_buffer::AudioRing,
+    vgm_file::VgmWriter,
+    wav_file::WavWriter,
+    Channel,
 };
 
+// Default output format
 pub const APU_FREQ: u32 = 44_100;
-pub const APU_SAMPLES_PER_CHANNEL: u16 = 4096;
+pub const APU_SAMPLES_PER_CHANNEL: u16 = 1024;
 pub const APU_NUM_CHANNELS: u8 = 2;
-pub const APU_SAMPLES: usize = APU_SAMPLES_PER_CHANNEL as usize * APU_NUM_CHANNELS as usize;
 
-pub type ApuSoundSender = SyncSender<Vec<i16>>;
+// Output format of the sound channel, the audio device has to be opened with the same settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    // 22050 - 96000 Hz
+    pub sample_rate: u32,
+    // Samples per channel the audio device requests at once, 256 - 8192
+    pub buffer_size: u16,
+    // 1 (mono) or 2 (stereo, interleaved)
+    pub num_channels: u8,
+    pub quality: AudioQuality,
+    pub high_pass: HighPassFilter,
+}
+
+impl AudioConfig {
+    pub fn new(
+        sample_rate: u32,
+        buffer_size: u16,
+        num_channels: u8,
+        quality: AudioQuality,
+        high_pass: HighPassFilter,
+    ) -> Self {
+        Self {
+            sample_rate: sample_rate.clamp(22_050, 96_000),
+            buffer_size: buffer_size.clamp(256, 8192),
+            num_channels: num_channels.clamp(1, 2),
+            quality,
+            high_pass,
+        }
+    }
+
+    // Interleaved samples in one device buffer
+    pub fn chunk_len(&self) -> usize {
+        usize::from(self.buffer_size) * usize::from(self.num_channels)
+    }
+}
+
+pub type ApuSoundSender = std::sync::Arc<AudioRing>;
 
-pub type AudioBuffer = Vec<u8>;
+pub type AudioBuffer = Vec<i16>;
 
 const T_CYCLES_PER_FRAME: u64 = (4_194_304.0 * (TARGET_FPS / GB_DEFAULT_FPS)) as u64;
 const FRAME_SEQUENCER_START: u16 = (T_CYCLES_PER_FRAME / (512.0 * 4.0) as u64) as u16;
-const SAMPLE_COUNTER_START: u16 = (T_CYCLES_PER_FRAME / APU_FREQ as f64 as u64) as u16;
 
-const RECORD_WAV_FILE: bool = false;
+// Mixer output (4 channels * ±1 * master volume) to i16, with room for the high-pass overshoot
+const OUTPUT_SCALE: f32 = 4096.0;
+
+// Frames handed to the ring buffer at once, small so that the fill level stays accurate
+const PUSH_FRAMES: usize = 64;
+
+// Largest deviation from the nominal rate when the ring buffer is empty or full. Enough to
+// follow a 60 Hz display, small enough that the pitch change is barely audible
+const MAX_RATE_DELTA: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApuChannel {
+    Pulse1 = 0,
+    Pulse2,
+    Wave,
+    Noise,
+}
 
 pub struct APU {
     channel1: Channel1,
@@ -28,12 +85,33 @@ pub struct APU {
     channel3: Channel3,
     channel4: Channel4,
 
-    sample_counter: u16,
-    sample_output: Option<(ApuSoundSender, AudioCVT)>,
+    sample_output: Option<ApuSoundSender>,
     sync_audio: bool,
-
-    wav_data: Vec<i16>,
+    audio_config: AudioConfig,
+
+    // Band-limited output, the last mixed amplitude is kept to detect changes
+    blip_left: BlipBuffer,
+    blip_right: BlipBuffer,
+    mix_left: f32,
+    mix_right: f32,
+    high_pass: HighPass,
+    dacs_enabled: bool,
+
+    // Channels which pass mute/solo, and whether any channel is being captured
+    audible: [bool; 4],
+    capture_active: bool,
+
+    // Recording of the output, a write error stops it and is kept until stop_recording
+    wav_writer: Option<WavWriter>,
+    wav_error: Option<std::io::Error>,
     sample_buffer: AudioBuffer,
+
+    // Log of register writes, errors are kept until stop_vgm_log like for recordings.
+    // The last write to each register (NR10 - wave RAM) is replayed when a log starts
+    vgm_writer: Option<VgmWriter>,
+    vgm_error: Option<std::io::Error>,
+    register_writes: [u8; 0x30],
+
     frame_sequencer: u16,
     frame_sequencer_step: u8,
 
@@ -52,21 +130,46 @@ impl APU {
     pub fn new(
         sound_chan: Option<ApuSoundSender>,
         sync_audio: bool,
+        audio_config: AudioConfig,
         ctx: std::rc::Rc<GbCtx>,
     ) -> Self {
-        let mut apu = Self {
+        // Fields are public, keep them in range anyway
+        let audio_config = AudioConfig::new(
+            audio_config.sample_rate,
+            audio_config.buffer_size,
+            audio_config.num_channels,
+            audio_config.quality,
+            audio_config.high_pass,
+        );
+
+        let clock_rate = T_CYCLES_PER_FRAME as f64;
+        let sample_rate = f64::from(audio_config.sample_rate);
+
+        Self {
             ctx: ctx.clone(),
             sync_audio,
-            sample_output: None,
+            sample_output: sound_chan,
+            audio_config,
+            blip_left: BlipBuffer::new(clock_rate, sample_rate, audio_config.quality),
+            blip_right: BlipBuffer::new(clock_rate, sample_rate, audio_config.quality),
+            mix_left: 0.0,
+            mix_right: 0.0,
+            high_pass: HighPass::new(audio_config.high_pass, ctx.model, clock_rate, sample_rate),
+            dacs_enabled: false,
+            audible: [true; 4],
+            capture_active: false,
             channel1: Channel1::new(ctx.clone()),
             channel2: Channel2::new(ctx.clone()),
             channel3: Channel3::new(ctx.clone()),
             channel4: Channel4::new(ctx.clone()),
-            sample_counter: SAMPLE_COUNTER_START,
             frame_sequencer: FRAME_SEQUENCER_START,
             frame_sequencer_step: 0,
-            sample_buffer: Vec::with_capacity(APU_SAMPLES),
-            wav_data: Vec::new(),
+            sample_buffer: Vec::with_capacity(PUSH_FRAMES * usize::from(audio_config.num_channels)),
+            wav_writer: None,
+            wav_error: None,
+            vgm_writer: None,
+            vgm_error: None,
+            register_writes: [0; 0x30],
             audio_enabled: true,
             right_pan: [true, true, false, false],
             right_vol: 7,
@@ -74,41 +177,169 @@ impl APU {
             left_vol: 7,
             left_vin: false,
             right_vin: false,
-        };
-
-        if let Some(channel) = sound_chan {
-            apu.sample_output = Some((channel, AudioCVT::new()));
         }
+    }
 
-        apu
+    // The SGB boot ROM doesn't play the startup sound, channel 1 is left off
+    pub fn skip_boot_sound(&mut self) {
+        self.channel1.disable();
     }
 
     pub fn close(&mut self) {
-        if RECORD_WAV_FILE {
-            if self.wav_data.len() == 0 {
-                return;
+        if self.is_recording() {
+            if let Err(err) = self.stop_recording() {
+                eprintln!("Unable to finish audio recording: {}", err);
+            }
+        }
+
+        if self.is_logging_vgm() {
+            if let Err(err) = self.stop_vgm_log() {
+                eprintln!("Unable to finish VGM log: {}", err);
+            }
+        }
+
+        for channel in self.get_channels() {
+            let output = channel.get_output();
+            if output.is_capturing_to_file() {
+                if let Err(err) = output.stop_capture() {
+                    eprintln!("Unable to finish channel capture: {}", err);
+                }
+            }
+        }
+    }
+
+    // Records the output in the same format as the sound channel
+    pub fn start_recording(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        if self.is_recording() {
+            self.stop_recording()?;
+        }
+
+        self.wav_writer = Some(WavWriter::create(
+            path,
+            self.audio_config.sample_rate,
+            self.audio_config.num_channels,
+        )?);
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        if let Some(err) = self.wav_error.take() {
+            return Err(err);
+        }
+
+        match self.wav_writer.take() {
+            Some(mut wav_writer) => {
+                wav_writer.write_samples(&self.sample_buffer)?;
+
+                // Nothing else consumes the buffer
+                if self.sample_output.is_none() {
+                    self.sample_buffer.clear();
+                }
+
+                wav_writer.finish()
             }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.wav_writer.is_some() || self.wav_error.is_some()
+    }
+
+    // Logs every register and wave RAM write with its timestamp to a VGM file
+    pub fn start_vgm_log(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        if self.is_logging_vgm() {
+            self.stop_vgm_log()?;
+        }
+
+        let mut vgm_writer = VgmWriter::create(path)?;
+
+        // The log may start in the middle of a song: replay the current state, powering on
+        // first and without retriggering channels through NRx4
+        let nr52 = (self.audio_enabled as u8) << 7;
+        vgm_writer.write_register(HWR_NR52, nr52)?;
+
+        let register_writes = self.register_writes;
+        for (offset, data) in register_writes.iter().enumerate() {
+            let address = HWR_NR10 + offset as u16;
+
+            let data = match address {
+                HWR_NR50 => self.read_nr50(),
+                HWR_NR51 => self.read_nr51(),
+                HWR_NR14 | HWR_NR24 | HWR_NR34 | HWR_NR44 => data & 0x7F,
+                // Unused addresses between NR52 and wave RAM
+                HWR_NR52..=0xFF2F => continue,
+                _ => *data,
+            };
+
+            vgm_writer.write_register(address, data)?;
+        }
+
+        self.vgm_writer = Some(vgm_writer);
+
+        Ok(())
+    }
+
+    pub fn stop_vgm_log(&mut self) -> std::io::Result<()> {
+        if let Some(err) = self.vgm_error.take() {
+            return Err(err);
+        }
 
-            write_wav("dev/test.wav", &self.wav_data);
+        match self.vgm_writer.take() {
+            Some(vgm_writer) => vgm_writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_logging_vgm(&self) -> bool {
+        self.vgm_writer.is_some() || self.vgm_error.is_some()
+    }
+
+    fn log_write(&mut self, address: u16, data: u8) {
+        self.register_writes[usize::from(address - HWR_NR10)] = data;
+
+        if let Some(vgm_writer) = &mut self.vgm_writer {
+            if let Err(err) = vgm_writer.write_register(address, data) {
+                self.vgm_writer = None;
+                self.vgm_error = Some(err);
+            }
         }
     }
 
     pub fn clock(&mut self) {
         self.frame_sequencer();
 
+        if let Some(vgm_writer) = &mut self.vgm_writer {
+            vgm_writer.clock(4);
+        }
+
+        // Nothing to synthesize for
+        let output = self.sample_output.is_some() || self.wav_writer.is_some();
+
         for _c in 0..4 {
             self.channel1.clock();
             self.channel2.clock();
             self.channel3.clock();
             self.channel4.clock();
 
-            self.sample_counter -= 1;
+            if self.capture_active {
+                for channel in self.get_channels() {
+                    let sample = channel.get_sample();
+                    channel.get_output().capture(sample);
+                }
+            }
 
-            if self.sample_counter == 0 {
-                self.sample_counter = SAMPLE_COUNTER_START;
-                self.sample_audio();
+            if output {
+                self.mix();
+                self.blip_left.clock(1);
+                self.blip_right.clock(1);
             }
         }
+
+        if output && self.blip_left.samples_avail() > 0 {
+            self.sample_audio();
+        }
     }
 
     pub fn frame_sequencer(&mut self) {
@@ -164,54 +395,119 @@ impl APU {
         }
     }
 
-    pub fn sample_audio(&mut self) {
-        let mut left_scaled = 0.0;
-        let mut right_scaled = 0.0;
+    // Adds a delta to the band-limited output whenever the mixed amplitude changes
+    fn mix(&mut self) {
+        let preserve = self.high_pass.filter() == HighPassFilter::Preserve;
+        let channels: [&dyn Channel; 4] = [
+            &self.channel1,
+            &self.channel2,
+            &self.channel3,
+            &self.channel4,
+        ];
 
-        for i in 0..4 {
-            let channel = &*self.get_channels()[i];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        let mut dacs_enabled = false;
+
+        for (i, channel) in channels.iter().enumerate() {
+            if !channel.is_dac_enabled() {
+                continue;
+            }
 
-            let sample = f32::from(channel.get_sample());
+            dacs_enabled = true;
+
+            if !self.audible[i] {
+                continue;
+            }
+
+            // An enabled DAC maps the digital 0-15 to the analog 1.0 - -1.0, a disabled DAC
+            // outputs 0. Preserve removes the offset of the enabled DAC
+            let sample = if preserve {
+                -f32::from(channel.get_sample()) / 7.5
+            } else {
+                1.0 - f32::from(channel.get_sample()) / 7.5
+            };
 
             if self.left_pan[i] {
-                left_scaled += sample * APU::get_volume_scale(self.left_vol);
+                left += sample;
             }
             if self.right_pan[i] {
-                right_scaled += sample * APU::get_volume_scale(self.right_vol);
+                right += sample;
             }
         }
 
-        if let Some((chan, cvt)) = &self.sample_output {
-            let left: u8 = left_scaled as u8;
-            let right: u8 = right_scaled as u8;
+        self.dacs_enabled = dacs_enabled;
 
-            self.sample_buffer.push(left);
-            self.sample_buffer.push(right);
+        left *= APU::get_volume_scale(self.left_vol);
+        right *= APU::get_volume_scale(self.right_vol);
 
-            if self.sample_buffer.len() < APU_SAMPLES {
-                return;
-            }
+        if left != self.mix_left {
+            self.blip_left.add_delta(left - self.mix_left);
+            self.mix_left = left;
+        }
+        if right != self.mix_right {
+            self.blip_right.add_delta(right - self.mix_right);
+            self.mix_right = right;
+        }
+    }
 
-            debug_assert!(self.sample_buffer.len() == APU_SAMPLES);
+    pub fn sample_audio(&mut self) {
+        let push_len = PUSH_FRAMES * usize::from(self.audio_config.num_channels);
+
+        while self.blip_left.samples_avail() > 0 {
+            let (left, right) = self.high_pass.apply(
+                self.blip_left.read_sample(),
+                self.blip_right.read_sample(),
+                self.dacs_enabled,
+            );
+            let (left, right) = (left * OUTPUT_SCALE, right * OUTPUT_SCALE);
+
+            if self.audio_config.num_channels == 1 {
+                let mono = (left + right) / 2.0;
+                self.sample_buffer
+                    .push(mono.clamp(-32768.0, 32767.0) as i16);
+            } else {
+                self.sample_buffer
+                    .push(left.clamp(-32768.0, 32767.0) as i16);
+                self.sample_buffer
+                    .push(right.clamp(-32768.0, 32767.0) as i16);
+            }
 
-            let cvt_audio = cvt.convert_u8_i16(&self.sample_buffer);
+            if self.sample_buffer.len() < push_len {
+                continue;
+            }
 
-            if RECORD_WAV_FILE {
-                for c in cvt_audio.iter() {
-                    self.wav_data.push(*c);
+            if let Some(wav_writer) = &mut self.wav_writer {
+                if let Err(err) = wav_writer.write_samples(&self.sample_buffer) {
+                    self.wav_writer = None;
+                    self.wav_error = Some(err);
                 }
             }
 
-            if self.sync_audio {
-                chan.send(cvt_audio).unwrap();
-            } else {
-                _ = chan.try_send(cvt_audio);
+            if let Some(ring) = &self.sample_output {
+                ring.push(&self.sample_buffer, self.sync_audio);
+                let fill_level = ring.fill_level();
+                self.rate_control(fill_level);
             }
+
             self.sample_buffer.clear();
         }
     }
 
+    // Dynamic rate control: produce slightly more samples while the ring buffer is below half
+    // and slightly less above, so that the emulator can be paced by video without under-runs
+    fn rate_control(&mut self, fill_level: f64) {
+        let clock_rate = T_CYCLES_PER_FRAME as f64;
+        let sample_rate = f64::from(self.audio_config.sample_rate)
+            * (1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * fill_level));
+
+        self.blip_left.set_ratio(clock_rate, sample_rate);
+        self.blip_right.set_ratio(clock_rate, sample_rate);
+    }
+
     pub fn write_nr50(&mut self, data: u8) {
+        self.log_write(HWR_NR50, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -223,6 +519,8 @@ impl APU {
     }
 
     pub fn write_nr51(&mut self, data: u8) {
+        self.log_write(HWR_NR51, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -234,6 +532,8 @@ impl APU {
     }
 
     pub fn write_nr52(&mut self, data: u8) {
+        self.log_write(HWR_NR52, data);
+
         let enable_bit = data & 0x80 != 0;
 
         match (self.audio_enabled, enable_bit) {
@@ -291,6 +591,8 @@ impl APU {
     }
 
     pub fn write_nr10(&mut self, data: u8) {
+        self.log_write(HWR_NR10, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -298,6 +600,8 @@ impl APU {
     }
 
     pub fn write_nr11(&mut self, data: u8) {
+        self.log_write(HWR_NR11, data);
+
         if !self.audio_enabled && self.ctx.cgb {
             return;
         }
@@ -310,6 +614,8 @@ impl APU {
     }
 
     pub fn write_nr12(&mut self, data: u8) {
+        self.log_write(HWR_NR12, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -317,6 +623,8 @@ impl APU {
     }
 
     pub fn write_nr13(&mut self, data: u8) {
+        self.log_write(HWR_NR13, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -324,6 +632,8 @@ impl APU {
     }
 
     pub fn write_nr14(&mut self, data: u8) {
+        self.log_write(HWR_NR14, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -331,6 +641,8 @@ impl APU {
     }
 
     pub fn write_nr21(&mut self, data: u8) {
+        self.log_write(HWR_NR21, data);
+
         if !self.audio_enabled && self.ctx.cgb {
             return;
         }
@@ -343,6 +655,8 @@ impl APU {
     }
 
     pub fn write_nr22(&mut self, data: u8) {
+        self.log_write(HWR_NR22, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -350,6 +664,8 @@ impl APU {
     }
 
     pub fn write_nr23(&mut self, data: u8) {
+        self.log_write(HWR_NR23, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -357,6 +673,8 @@ impl APU {
     }
 
     pub fn write_nr24(&mut self, data: u8) {
+        self.log_write(HWR_NR24, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -364,10 +682,14 @@ impl APU {
     }
 
     pub fn write_wave_ram(&mut self, address: u16, data: u8) {
+        self.log_write(address, data);
+
         self.channel3.write_wave_ram(usize::from(address), data);
     }
 
     pub fn write_nr30(&mut self, data: u8) {
+        self.log_write(HWR_NR30, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -375,6 +697,8 @@ impl APU {
     }
 
     pub fn write_nr31(&mut self, data: u8) {
+        self.log_write(HWR_NR31, data);
+
         if !self.audio_enabled && self.ctx.cgb {
             return;
         }
@@ -383,6 +707,8 @@ impl APU {
     }
 
     pub fn write_nr32(&mut self, data: u8) {
+        self.log_write(HWR_NR32, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -390,6 +716,8 @@ impl APU {
     }
 
     pub fn write_nr33(&mut self, data: u8) {
+        self.log_write(HWR_NR33, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -397,6 +725,8 @@ impl APU {
     }
 
     pub fn write_nr34(&mut self, data: u8) {
+        self.log_write(HWR_NR34, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -404,6 +734,8 @@ impl APU {
     }
 
     pub fn write_nr41(&mut self, data: u8) {
+        self.log_write(HWR_NR41, data);
+
         if !self.audio_enabled && self.ctx.cgb {
             return;
         }
@@ -412,6 +744,8 @@ impl APU {
     }
 
     pub fn write_nr42(&mut self, data: u8) {
+        self.log_write(HWR_NR42, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -419,6 +753,8 @@ impl APU {
     }
 
     pub fn write_nr43(&mut self, data: u8) {
+        self.log_write(HWR_NR43, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -426,6 +762,8 @@ impl APU {
     }
 
     pub fn write_nr44(&mut self, data: u8) {
+        self.log_write(HWR_NR44, data);
+
         if !self.audio_enabled {
             return;
         }
@@ -504,14 +842,123 @@ impl APU {
         self.channel4.read_nr44()
     }
 
+    // CGB PCM12 (FF76): digital outputs of channel 1 (low nibble) and 2 (high nibble)
+    pub fn read_pcm12(&self) -> u8 {
+        APU::pcm_output(&self.channel1) | (APU::pcm_output(&self.channel2) << 4)
+    }
+
+    // CGB PCM34 (FF77): digital outputs of channel 3 (low nibble) and 4 (high nibble)
+    pub fn read_pcm34(&self) -> u8 {
+        APU::pcm_output(&self.channel3) | (APU::pcm_output(&self.channel4) << 4)
+    }
+
+    fn pcm_output(channel: &dyn Channel) -> u8 {
+        if channel.is_enabled() {
+            channel.get_sample() & 0xF
+        } else {
+            0
+        }
+    }
+
     pub fn read_wave_ram(&mut self, address: u16) -> u8 {
         self.channel3.read_wave_ram(usize::from(address))
     }
 
+    pub fn set_channel_muted(&mut self, channel: ApuChannel, muted: bool) {
+        self.get_channels()[channel as usize].get_output().muted = muted;
+        self.update_audible();
+    }
+
+    pub fn is_channel_muted(&self, channel: ApuChannel) -> bool {
+        self.get_channel(channel).get_output_ref().muted
+    }
+
+    // While any channel is soloed only soloed channels are heard
+    pub fn set_channel_soloed(&mut self, channel: ApuChannel, soloed: bool) {
+        self.get_channels()[channel as usize].get_output().soloed = soloed;
+        self.update_audible();
+    }
+
+    pub fn is_channel_soloed(&self, channel: ApuChannel) -> bool {
+        self.get_channel(channel).get_output_ref().soloed
+    }
+
+    // Captures the channel on its own, mono at the output sample rate
+    pub fn start_channel_capture(&mut self, channel: ApuChannel) {
+        self.start_capture(channel, None);
+    }
+
+    // Streams the capture of the channel to a mono WAV file
+    pub fn start_channel_capture_file(
+        &mut self,
+        channel: ApuChannel,
+        path: &std::path::Path,
+    ) -> std::io::Result<()> {
+        self.stop_channel_capture(channel)?;
+
+        let wav_writer = WavWriter::create(path, self.audio_config.sample_rate, 1)?;
+        self.start_capture(channel, Some(wav_writer));
+
+        Ok(())
+    }
+
+    fn start_capture(&mut self, channel: ApuChannel, wav_writer: Option<WavWriter>) {
+        let clock_rate = T_CYCLES_PER_FRAME as f64;
+        let sample_rate = f64::from(self.audio_config.sample_rate);
+        let quality = self.audio_config.quality;
+
+        self.get_channels()[channel as usize]
+            .get_output()
+            .start_capture(clock_rate, sample_rate, quality, wav_writer);
+        self.capture_active = true;
+    }
+
+    pub fn stop_channel_capture(&mut self, channel: ApuChannel) -> std::io::Result<Vec<i16>> {
+        let samples = self.get_channels()[channel as usize]
+            .get_output()
+            .stop_capture();
+        self.capture_active = self
+            .get_channels()
+            .iter_mut()
+            .any(|channel| channel.get_output().is_capturing());
+
+        samples
+    }
+
+    pub fn take_channel_capture(&mut self, channel: ApuChannel) -> Vec<i16> {
+        self.get_channels()[channel as usize]
+            .get_output()
+            .take_capture()
+    }
+
+    fn update_audible(&mut self) {
+        let mut channels = self.get_channels();
+        let any_soloed = channels
+            .iter_mut()
+            .any(|channel| channel.get_output().soloed);
+
+        let mut audible = [true; 4];
+        for (audible, channel) in audible.iter_mut().zip(channels.iter_mut()) {
+            let output = channel.get_output();
+            *audible = !output.muted && (!any_soloed || output.soloed);
+        }
+
+        self.audible = audible;
+    }
+
     fn get_volume_scale(vol: u8) -> f32 {
         f32::from(vol + 1) / 8.0
     }
 
+    fn get_channel(&self, channel: ApuChannel) -> &dyn Channel {
+        match channel {
+            ApuChannel::Pulse1 => &self.channel1,
+            ApuChannel::Pulse2 => &self.channel2,
+            ApuChannel::Wave => &self.channel3,
+            ApuChannel::Noise => &self.channel4,
+        }
+    }
+
     fn get_channels(&mut self) -> [&mut dyn Channel; 4] {
         [
             &mut self.channel1,
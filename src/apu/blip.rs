@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+// Band-limited step synthesis, in the spirit of blargg's Blip_Buffer.
+// Amplitude changes are added as deltas at the exact clock they happen, each delta is spread
+// over a few output samples by a windowed sinc kernel and the result is integrated back into
+// a waveform. Steps come out without the aliasing of point sampling.
+
+// Fraction of the output rate that passes through the kernel, the rest is rolled off
+const CUTOFF: f64 = 0.45;
+
+// Kernel width in output samples and number of sub-sample phases
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl AudioQuality {
+    fn kernel_size(&self) -> (usize, usize) {
+        match self {
+            AudioQuality::Low => (8, 32),
+            AudioQuality::Medium => (16, 64),
+            AudioQuality::High => (32, 128),
+        }
+    }
+}
+
+pub struct BlipBuffer {
+    // Output samples per input clock
+    ratio: f64,
+    // Current position in output samples, relative to the first pending sample
+    time: f64,
+    deltas: VecDeque<f32>,
+    integrator: f32,
+
+    width: usize,
+    phases: usize,
+    kernel: Vec<f32>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64, quality: AudioQuality) -> Self {
+        let (width, phases) = quality.kernel_size();
+
+        Self {
+            ratio: sample_rate / clock_rate,
+            time: 0.0,
+            deltas: VecDeque::from(vec![0.0; width * 2]),
+            integrator: 0.0,
+            width,
+            phases,
+            kernel: BlipBuffer::build_kernel(width, phases),
+        }
+    }
+
+    // Windowed sinc impulse for every phase, each phase sums up to 1 so steps settle exactly
+    fn build_kernel(width: usize, phases: usize) -> Vec<f32> {
+        let mut kernel = vec![0.0; width * phases];
+        let center = (width / 2) as f64;
+
+        for phase in 0..phases {
+            let offset = phase as f64 / phases as f64;
+            let taps = &mut kernel[phase * width..(phase + 1) * width];
+
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - center - offset + 1.0;
+                let sinc = if x == 0.0 {
+                    2.0 * CUTOFF
+                } else {
+                    (2.0 * std::f64::consts::PI * CUTOFF * x).sin() / (std::f64::consts::PI * x)
+                };
+
+                // Blackman window over the kernel width
+                let w = (k as f64 + 1.0 - offset) / width as f64;
+                let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * w).cos()
+                    + 0.08 * (4.0 * std::f64::consts::PI * w).cos();
+
+                *tap = (sinc * window) as f32;
+            }
+
+            let sum: f32 = taps.iter().sum();
+            for tap in taps.iter_mut() {
+                *tap /= sum;
+            }
+        }
+
+        kernel
+    }
+
+    pub fn set_ratio(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.ratio = sample_rate / clock_rate;
+    }
+
+    // Amplitude change at the current time
+    pub fn add_delta(&mut self, delta: f32) {
+        let index = self.time as usize;
+        let phase = ((self.time - index as f64) * self.phases as f64) as usize;
+
+        if self.deltas.len() < index + self.width {
+            self.deltas.resize(index + self.width * 2, 0.0);
+        }
+
+        let taps = &self.kernel[phase * self.width..(phase + 1) * self.width];
+        for (delta_out, tap) in self.deltas.range_mut(index..index + self.width).zip(taps) {
+            *delta_out += delta * tap;
+        }
+    }
+
+    pub fn clock(&mut self, clocks: u32) {
+        self.time += f64::from(clocks) * self.ratio;
+    }
+
+    // Samples which can't be changed by deltas added from now on
+    pub fn samples_avail(&self) -> usize {
+        self.time as usize
+    }
+
+    pub fn read_sample(&mut self) -> f32 {
+        debug_assert!(self.samples_avail() > 0);
+
+        self.integrator += self.deltas.pop_front().unwrap_or(0.0);
+        self.deltas.push_back(0.0);
+        self.time -= 1.0;
+
+        self.integrator
+    }
+}
@@ -1,6 +1,8 @@
 use crate::GbCtx;
 
-use super::{envelope::Envelope, lengthcounter::LengthCounter, sweep::Sweep, Channel};
+use super::{
+    envelope::Envelope, lengthcounter::LengthCounter, output::ChannelOutput, sweep::Sweep, Channel,
+};
 
 const LENGTH_COUNTER_INIT: u16 = 64;
 
@@ -28,6 +30,7 @@ pub struct Channel1 {
     reg_dac_enable: bool,
 
     sample: u8,
+    output: ChannelOutput,
 }
 
 impl Channel1 {
@@ -42,9 +45,14 @@ impl Channel1 {
             reg_waveduty: 2,
             reg_dac_enable: true,
             sample: 0,
+            output: ChannelOutput::new(),
         }
     }
 
+    pub fn disable(&mut self) {
+        self.is_enabled = false;
+    }
+
     pub fn sweep_clock(&mut self) {
         let is_overflow = self.sweep.clock();
 
@@ -164,6 +172,14 @@ impl Channel for Channel1 {
         self.sample
     }
 
+    fn get_output(&mut self) -> &mut ChannelOutput {
+        &mut self.output
+    }
+
+    fn get_output_ref(&self) -> &ChannelOutput {
+        &self.output
+    }
+
     fn get_length_counter(&mut self) -> &mut LengthCounter {
         &mut self.length_counter
     }
@@ -176,6 +192,10 @@ impl Channel for Channel1 {
         self.is_enabled && self.reg_dac_enable
     }
 
+    fn is_dac_enabled(&self) -> bool {
+        self.reg_dac_enable
+    }
+
     fn shutdown(&mut self) {
         self.is_enabled = false;
         self.reg_dac_enable = false;
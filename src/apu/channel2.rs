@@ -1,6 +1,6 @@
 use crate::GbCtx;
 
-use super::{envelope::Envelope, lengthcounter::LengthCounter, Channel};
+use super::{envelope::Envelope, lengthcounter::LengthCounter, output::ChannelOutput, Channel};
 
 const LENGTH_COUNTER_INIT: u16 = 64;
 
@@ -28,6 +28,7 @@ pub struct Channel2 {
     reg_dac_enable: bool,
 
     sample: u8,
+    output: ChannelOutput,
 }
 
 impl Channel2 {
@@ -42,6 +43,7 @@ impl Channel2 {
             reg_waveduty: 0,
             reg_dac_enable: false,
             sample: 0,
+            output: ChannelOutput::new(),
         }
     }
 
@@ -138,6 +140,14 @@ impl Channel for Channel2 {
         self.sample
     }
 
+    fn get_output(&mut self) -> &mut ChannelOutput {
+        &mut self.output
+    }
+
+    fn get_output_ref(&self) -> &ChannelOutput {
+        &self.output
+    }
+
     fn get_length_counter(&mut self) -> &mut LengthCounter {
         &mut self.length_counter
     }
@@ -150,6 +160,10 @@ impl Channel for Channel2 {
         self.is_enabled && self.reg_dac_enable
     }
 
+    fn is_dac_enabled(&self) -> bool {
+        self.reg_dac_enable
+    }
+
     fn shutdown(&mut self) {
         self.is_enabled = false;
         self.reg_dac_enable = false;
@@ -1,6 +1,6 @@
-use crate::GbCtx;
+use crate::{GbCtx, HardwareModel};
 
-use super::{lengthcounter::LengthCounter, Channel};
+use super::{lengthcounter::LengthCounter, output::ChannelOutput, Channel};
 
 const LENGTH_COUNTER_INIT: u16 = 256;
 
@@ -19,6 +19,7 @@ pub struct Channel3 {
 
     wave_ram: [u8; 16],
     sample: u8,
+    output: ChannelOutput,
 
     ctx: std::rc::Rc<GbCtx>,
 }
@@ -37,6 +38,7 @@ impl Channel3 {
             reg_frequency: 0,
             wave_ram: [0; 16],
             sample: 0,
+            output: ChannelOutput::new(),
         }
     }
 
@@ -49,7 +51,7 @@ impl Channel3 {
         // To avoid this corruption you should stop the wave by writing 0 then $80 to NR30 before triggering it again.
         // The game Duck Tales encounters this issue part way through most songs.
 
-        if self.freq_timer == 2 && self.is_enabled() && !self.ctx.cgb {
+        if self.freq_timer == 2 && self.is_enabled() && self.has_dmg_wave_quirks() {
             let next_byte_index = (self.sample_index / 2) as u8;
 
             if next_byte_index < 4 {
@@ -109,18 +111,11 @@ impl Channel3 {
     }
 
     pub fn write_wave_ram(&mut self, addr: usize, data: u8) {
-        if self.is_enabled() {
-            if self.last_sample_step < 2 || self.ctx.cgb {
-                let last_sample_index = if self.sample_index == 0 {
-                    31
-                } else {
-                    self.sample_index - 1
-                };
-                self.wave_ram[last_sample_index / 2] = data;
-            }
-            return;
+        if !self.is_enabled() {
+            self.wave_ram[addr & 0xF] = data;
+        } else if let Some(index) = self.playing_wave_index() {
+            self.wave_ram[index] = data;
         }
-        self.wave_ram[addr & 0xF] = data;
     }
 
     pub fn read_nr30(&mut self) -> u8 {
@@ -146,18 +141,43 @@ impl Channel3 {
     }
 
     pub fn read_wave_ram(&mut self, addr: usize) -> u8 {
-        if self.is_enabled() {
-            if self.last_sample_step < 2 || self.ctx.cgb {
-                let last_sample_index = if self.sample_index == 0 {
-                    31
-                } else {
-                    self.sample_index - 1
-                };
-                return self.wave_ram[last_sample_index / 2];
-            }
-            return 0xFF;
+        if !self.is_enabled() {
+            return self.wave_ram[addr & 0xF];
+        }
+
+        match self.playing_wave_index() {
+            Some(index) => self.wave_ram[index],
+            None => 0xFF,
+        }
+    }
+
+    // While the channel plays, the CPU accesses the byte the channel read last regardless of
+    // the address. On DMG this only works in the same cycle as the channel's read, otherwise
+    // reads return 0xFF and writes are ignored
+    fn playing_wave_index(&self) -> Option<usize> {
+        if self.last_sample_step >= 2 && self.has_dmg_wave_quirks() {
+            return None;
+        }
+
+        let last_sample_index = if self.sample_index == 0 {
+            31
+        } else {
+            self.sample_index - 1
+        };
+
+        Some(last_sample_index / 2)
+    }
+
+    // The wave RAM quirks depend on the hardware, CGB and AGB don't have them in DMG mode either
+    fn has_dmg_wave_quirks(&self) -> bool {
+        match self.ctx.model {
+            HardwareModel::Dmg0
+            | HardwareModel::Dmg
+            | HardwareModel::Mgb
+            | HardwareModel::Sgb
+            | HardwareModel::Sgb2 => true,
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => false,
         }
-        self.wave_ram[addr & 0xF]
     }
 }
 
@@ -207,6 +227,14 @@ impl Channel for Channel3 {
         self.sample
     }
 
+    fn get_output(&mut self) -> &mut ChannelOutput {
+        &mut self.output
+    }
+
+    fn get_output_ref(&self) -> &ChannelOutput {
+        &self.output
+    }
+
     fn get_length_counter(&mut self) -> &mut LengthCounter {
         &mut self.length_counter
     }
@@ -219,6 +247,10 @@ impl Channel for Channel3 {
         self.is_enabled && self.reg_dac_enable
     }
 
+    fn is_dac_enabled(&self) -> bool {
+        self.reg_dac_enable
+    }
+
     fn shutdown(&mut self) {
         self.is_enabled = false;
         self.reg_dac_enable = false;
@@ -1,6 +1,6 @@
 use crate::GbCtx;
 
-use super::{envelope::Envelope, lengthcounter::LengthCounter, Channel};
+use super::{envelope::Envelope, lengthcounter::LengthCounter, output::ChannelOutput, Channel};
 
 const LENGTH_COUNTER_INIT: u16 = 64;
 
@@ -21,6 +21,7 @@ pub struct Channel4 {
     reg_divisor: u8,
 
     sample: u8,
+    output: ChannelOutput,
 }
 
 impl Channel4 {
@@ -36,6 +37,7 @@ impl Channel4 {
             is_enabled: false,
             reg_dac_enable: false,
             sample: 0,
+            output: ChannelOutput::new(),
         }
     }
 
@@ -142,6 +144,14 @@ impl Channel for Channel4 {
         self.sample
     }
 
+    fn get_output(&mut self) -> &mut ChannelOutput {
+        &mut self.output
+    }
+
+    fn get_output_ref(&self) -> &ChannelOutput {
+        &self.output
+    }
+
     fn get_length_counter(&mut self) -> &mut LengthCounter {
         &mut self.length_counter
     }
@@ -154,6 +164,10 @@ impl Channel for Channel4 {
         self.is_enabled && self.reg_dac_enable
     }
 
+    fn is_dac_enabled(&self) -> bool {
+        self.reg_dac_enable
+    }
+
     fn shutdown(&mut self) {
         self.is_enabled = false;
         self.reg_dac_enable = false;
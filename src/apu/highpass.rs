@@ -0,0 +1,66 @@
+use crate::gameboy::gameboy::HardwareModel;
+
+// How the DC offset of the DACs is removed from the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighPassFilter {
+    // Capacitor of the model's analog output, also bends low frequencies like the hardware
+    Accurate,
+    // Only the offset of the enabled DACs is subtracted, the waveform is left unaltered
+    Preserve,
+    // Raw DAC output, including the DC bias and the pops when a DAC is turned on or off
+    Off,
+}
+
+// Capacitor charge factors per T-cycle, from Pan Docs
+const CHARGE_FACTOR_DMG: f64 = 0.999958;
+const CHARGE_FACTOR_CGB: f64 = 0.998943;
+
+pub struct HighPass {
+    filter: HighPassFilter,
+    charge_factor: f32,
+    capacitor_left: f32,
+    capacitor_right: f32,
+}
+
+impl HighPass {
+    pub fn new(
+        filter: HighPassFilter,
+        model: HardwareModel,
+        clock_rate: f64,
+        sample_rate: f64,
+    ) -> Self {
+        let charge_factor = match model {
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => CHARGE_FACTOR_CGB,
+            _ => CHARGE_FACTOR_DMG,
+        };
+
+        Self {
+            filter,
+            charge_factor: charge_factor.powf(clock_rate / sample_rate) as f32,
+            capacitor_left: 0.0,
+            capacitor_right: 0.0,
+        }
+    }
+
+    pub fn filter(&self) -> HighPassFilter {
+        self.filter
+    }
+
+    // Called once per output sample. The capacitor only charges while a DAC is enabled
+    pub fn apply(&mut self, left: f32, right: f32, dacs_enabled: bool) -> (f32, f32) {
+        if self.filter != HighPassFilter::Accurate {
+            return (left, right);
+        }
+
+        if !dacs_enabled {
+            return (0.0, 0.0);
+        }
+
+        let out_left = left - self.capacitor_left;
+        let out_right = right - self.capacitor_right;
+        self.capacitor_left = left - out_left * self.charge_factor;
+        self.capacitor_right = right - out_right * self.charge_factor;
+
+        (out_left, out_right)
+    }
+}
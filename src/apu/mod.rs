@@ -1,12 +1,16 @@
 pub mod apu;
-mod audiocvt;
+pub mod blip;
 mod channel1;
 mod channel2;
 mod channel3;
 mod channel4;
 mod envelope;
+pub mod highpass;
 mod lengthcounter;
+mod output;
+pub mod ring_buffer;
 mod sweep;
+pub mod vgm_file;
 mod wav_file;
 
 pub trait Channel {
@@ -15,6 +19,9 @@ pub trait Channel {
     fn get_sample(&self) -> u8;
     fn get_length_counter(&mut self) -> &mut lengthcounter::LengthCounter;
     fn get_envelope(&mut self) -> Option<&mut envelope::Envelope>;
+    fn get_output(&mut self) -> &mut output::ChannelOutput;
+    fn get_output_ref(&self) -> &output::ChannelOutput;
     fn is_enabled(&self) -> bool;
+    fn is_dac_enabled(&self) -> bool;
     fn shutdown(&mut self);
 }
@@ -0,0 +1,131 @@
+use std::io;
+
+use super::{
+    blip::{AudioQuality, BlipBuffer},
+    wav_file::WavWriter,
+};
+
+// 4-bit channel output (0-15) to i16, with headroom for the ringing of band-limited steps
+const CAPTURE_SCALE: f32 = 1792.0;
+
+// Samples kept until taken, newer ones are dropped once reached
+const CAPTURE_MAX_SECONDS: usize = 60;
+
+// Captures to a file are written in blocks of samples
+const CAPTURE_FILE_BLOCK: usize = 4096;
+
+// Mute/solo state of a single channel, applied by the mixer, and an optional capture of
+// the channel on its own. Captures are taken before panning, master volume and muting
+pub struct ChannelOutput {
+    pub muted: bool,
+    pub soloed: bool,
+    capture: Option<ChannelCapture>,
+}
+
+struct ChannelCapture {
+    blip: BlipBuffer,
+    last_sample: u8,
+    samples: Vec<i16>,
+    max_samples: usize,
+    // Mono WAV file the samples are streamed to instead of being kept
+    wav_writer: Option<WavWriter>,
+    wav_error: Option<io::Error>,
+}
+
+impl ChannelOutput {
+    pub fn new() -> Self {
+        Self {
+            muted: false,
+            soloed: false,
+            capture: None,
+        }
+    }
+
+    pub fn start_capture(
+        &mut self,
+        clock_rate: f64,
+        sample_rate: f64,
+        quality: AudioQuality,
+        wav_writer: Option<WavWriter>,
+    ) {
+        self.capture = Some(ChannelCapture {
+            blip: BlipBuffer::new(clock_rate, sample_rate, quality),
+            last_sample: 0,
+            samples: Vec::new(),
+            max_samples: sample_rate as usize * CAPTURE_MAX_SECONDS,
+            wav_writer,
+            wav_error: None,
+        });
+    }
+
+    // Returns the samples captured since the last take, nothing when captured to a file
+    pub fn stop_capture(&mut self) -> io::Result<Vec<i16>> {
+        let mut capture = match self.capture.take() {
+            Some(capture) => capture,
+            None => return Ok(Vec::new()),
+        };
+
+        if let Some(err) = capture.wav_error.take() {
+            return Err(err);
+        }
+
+        match capture.wav_writer.take() {
+            Some(mut wav_writer) => {
+                wav_writer.write_samples(&capture.samples)?;
+                wav_writer.finish()?;
+                Ok(Vec::new())
+            }
+            None => Ok(capture.samples),
+        }
+    }
+
+    pub fn is_capturing_to_file(&self) -> bool {
+        match &self.capture {
+            Some(capture) => capture.wav_writer.is_some() || capture.wav_error.is_some(),
+            None => false,
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Mono samples at the output rate captured since the last take
+    pub fn take_capture(&mut self) -> Vec<i16> {
+        match &mut self.capture {
+            Some(capture) if capture.wav_writer.is_none() => std::mem::take(&mut capture.samples),
+            _ => Vec::new(),
+        }
+    }
+
+    // Called every T-cycle with the current channel sample
+    pub fn capture(&mut self, sample: u8) {
+        if let Some(capture) = &mut self.capture {
+            if sample != capture.last_sample {
+                capture
+                    .blip
+                    .add_delta(f32::from(sample) - f32::from(capture.last_sample));
+                capture.last_sample = sample;
+            }
+
+            capture.blip.clock(1);
+
+            while capture.blip.samples_avail() > 0 {
+                let sample = capture.blip.read_sample() * CAPTURE_SCALE;
+                if capture.samples.len() < capture.max_samples {
+                    capture.samples.push(sample.clamp(-32768.0, 32767.0) as i16);
+                }
+            }
+
+            if capture.samples.len() >= CAPTURE_FILE_BLOCK {
+                if let Some(wav_writer) = &mut capture.wav_writer {
+                    if let Err(err) = wav_writer.write_samples(&capture.samples) {
+                        capture.wav_writer = None;
+                        capture.wav_error = Some(err);
+                    }
+                    capture.samples.clear();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time,
+};
+
+// Longest time a blocking push waits for the audio device, in case playback has stopped
+const PUSH_TIMEOUT: time::Duration = time::Duration::from_millis(100);
+
+// Mono or stereo, like AudioConfig
+const MAX_CHANNELS: usize = 2;
+
+// Interleaved samples shared between the emulator (producer) and the audio device (consumer)
+pub struct AudioRing {
+    samples: Mutex<VecDeque<i16>>,
+    space_avail: Condvar,
+    capacity: usize,
+    num_channels: usize,
+    // Repeated on underrun so that the output doesn't jump to 0 and click
+    last_frame: Mutex<[i16; MAX_CHANNELS]>,
+}
+
+impl AudioRing {
+    pub fn new(capacity: usize, num_channels: u8) -> Self {
+        assert!(
+            (1..=MAX_CHANNELS).contains(&usize::from(num_channels)),
+            "audio ring supports 1 or 2 channels, got {num_channels}"
+        );
+
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            space_avail: Condvar::new(),
+            capacity,
+            num_channels: usize::from(num_channels),
+            last_frame: Mutex::new([0; MAX_CHANNELS]),
+        }
+    }
+
+    // When wait is set the producer blocks until everything fits, otherwise the excess is dropped
+    pub fn push(&self, samples: &[i16], wait: bool) {
+        let mut ring = self.samples.lock().unwrap();
+
+        if wait {
+            while self.capacity - ring.len() < samples.len() {
+                let (guard, timeout) = self.space_avail.wait_timeout(ring, PUSH_TIMEOUT).unwrap();
+                ring = guard;
+
+                if timeout.timed_out() {
+                    break;
+                }
+            }
+        }
+
+        let free = self.capacity.saturating_sub(ring.len());
+        ring.extend(&samples[..samples.len().min(free)]);
+    }
+
+    // Returns false if the ring ran dry and the rest of out was filled with the last frame
+    pub fn pop(&self, out: &mut [i16]) -> bool {
+        let mut ring = self.samples.lock().unwrap();
+        let mut last_frame = self.last_frame.lock().unwrap();
+
+        let avail = ring.len().min(out.len());
+        let avail = avail - avail % self.num_channels;
+
+        for (dst, src) in out.iter_mut().zip(ring.drain(..avail)) {
+            *dst = src;
+        }
+
+        if avail >= self.num_channels {
+            last_frame[..self.num_channels].copy_from_slice(&out[avail - self.num_channels..avail]);
+        }
+
+        for (i, dst) in out[avail..].iter_mut().enumerate() {
+            *dst = last_frame[i % self.num_channels];
+        }
+
+        self.space_avail.notify_one();
+
+        avail == out.len()
+    }
+
+    // 0.0 (empty) - 1.0 (full)
+    pub fn fill_level(&self) -> f64 {
+        self.samples.lock().unwrap().len() as f64 / self.capacity as f64
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// VGM 1.61 is the first version with the GB DMG chip
+const VGM_VERSION: u32 = 0x161;
+const HEADER_SIZE: u32 = 0x100;
+
+// Offsets of the header fields which are only known when the log ends
+const EOF_OFFSET: u64 = 0x04;
+const TOTAL_SAMPLES_OFFSET: u64 = 0x18;
+
+// The data offset is stored relative to its own position
+const DATA_OFFSET_OFFSET: usize = 0x34;
+const DMG_CLOCK_OFFSET: usize = 0x80;
+
+const DMG_CLOCK: u32 = 4_194_304;
+// Waits are in samples at 44.1 kHz regardless of the output rate
+const VGM_SAMPLE_RATE: u64 = 44_100;
+
+const CMD_DMG_WRITE: u8 = 0xB3;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC_FRAME: u8 = 0x62;
+const CMD_WAIT_PAL_FRAME: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+// Log of APU register writes as a VGM file. Register addresses are stored relative to NR10,
+// which places wave RAM at 0x20-0x2F like in the VGM specification
+pub struct VgmWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+    // T-cycles since the log was started and samples already waited for
+    cycles: u64,
+    samples: u64,
+}
+
+impl VgmWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[0x00..0x04].copy_from_slice(b"Vgm ");
+        header[0x08..0x0C].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        header[DATA_OFFSET_OFFSET..DATA_OFFSET_OFFSET + 4]
+            .copy_from_slice(&(HEADER_SIZE - DATA_OFFSET_OFFSET as u32).to_le_bytes());
+        header[DMG_CLOCK_OFFSET..DMG_CLOCK_OFFSET + 4].copy_from_slice(&DMG_CLOCK.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file,
+            data_size: 0,
+            cycles: 0,
+            samples: 0,
+        })
+    }
+
+    pub fn clock(&mut self, t_cycles: u64) {
+        self.cycles += t_cycles;
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) -> io::Result<()> {
+        self.wait()?;
+        self.write_command(&[CMD_DMG_WRITE, (address - 0xFF10) as u8, data])
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.wait()?;
+        self.write_command(&[CMD_END])?;
+
+        self.file.seek(SeekFrom::Start(EOF_OFFSET))?;
+        self.file
+            .write_all(&(HEADER_SIZE + self.data_size - 4).to_le_bytes())?;
+
+        self.file.seek(SeekFrom::Start(TOTAL_SAMPLES_OFFSET))?;
+        self.file
+            .write_all(&(self.samples.min(u64::from(u32::MAX)) as u32).to_le_bytes())?;
+
+        self.file.flush()
+    }
+
+    // Waits until the sample of the current cycle
+    fn wait(&mut self) -> io::Result<()> {
+        let target = self.cycles * VGM_SAMPLE_RATE / u64::from(DMG_CLOCK);
+
+        while self.samples < target {
+            let pending = (target - self.samples).min(u64::from(u16::MAX));
+
+            match pending {
+                735 => self.write_command(&[CMD_WAIT_NTSC_FRAME])?,
+                882 => self.write_command(&[CMD_WAIT_PAL_FRAME])?,
+                1..=16 => self.write_command(&[CMD_WAIT_SHORT + (pending - 1) as u8])?,
+                _ => {
+                    let [lo, hi] = (pending as u16).to_le_bytes();
+                    self.write_command(&[CMD_WAIT, lo, hi])?
+                }
+            }
+
+            self.samples += pending;
+        }
+
+        Ok(())
+    }
+
+    fn write_command(&mut self, command: &[u8]) -> io::Result<()> {
+        let size = command.len() as u32;
+
+        if u32::MAX - HEADER_SIZE - self.data_size < size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "VGM file size limit reached",
+            ));
+        }
+
+        self.file.write_all(command)?;
+        self.data_size += size;
+
+        Ok(())
+    }
+}
@@ -1,70 +1,82 @@
-use std::{fs, io::Write};
-
-pub fn write_wav(file_name: &str, wav_data: &Vec<i16>) {
-    let mut wav_file = Vec::new();
-
-    let num_samples = wav_data.len() / 2;
-    let file_size = (num_samples * 4 + 36) as u32;
-
-    // [Master RIFF chunk]
-    wav_file.write("RIFF".as_bytes()).unwrap();
-    wav_file.write(&[((file_size >> 0) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((file_size >> 8) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((file_size >> 16) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((file_size >> 24) & 0xFF) as u8]).unwrap();
-
-    wav_file.write("WAVE".as_bytes()).unwrap();
-
-    // [Chunk describing the data format]
-    wav_file.write("fmt ".as_bytes()).unwrap();
-
-    // format size
-    wav_file.write(&[0x10]).unwrap();
-    wav_file.write(&[0]).unwrap();
-    wav_file.write(&[0]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // format PCM
-    wav_file.write(&[1]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // Num Channels
-    wav_file.write(&[2]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // rate=44100
-    wav_file.write(&[0x44]).unwrap();
-    wav_file.write(&[0xAC]).unwrap();
-    wav_file.write(&[0]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // rate*2*2
-    wav_file.write(&[0x10]).unwrap();
-    wav_file.write(&[0xB1]).unwrap();
-    wav_file.write(&[0x2]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // bytes per sample
-    wav_file.write(&[4]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // bits per sample
-    wav_file.write(&[0x10]).unwrap();
-    wav_file.write(&[0]).unwrap();
-
-    // [Chunk containing the sampled data]
-    wav_file.write("data".as_bytes()).unwrap();
-    let data_size = num_samples * 4;
-    wav_file.write(&[((data_size >> 0) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((data_size >> 8) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((data_size >> 16) & 0xFF) as u8]).unwrap();
-    wav_file.write(&[((data_size >> 24) & 0xFF) as u8]).unwrap();
-
-    for s in wav_data {
-        wav_file.write(&[(*s & 0xFF) as u8]).unwrap();
-        wav_file.write(&[(*s >> 8) as u8]).unwrap();
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const HEADER_SIZE: u32 = 44;
+
+// Offsets of the sizes which are only known when the recording ends
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+
+// 16-bit PCM WAV file written while recording. The header is written with empty sizes
+// and patched in finish
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, num_channels: u8) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let block_align = u16::from(num_channels) * 2;
+
+        // [Master RIFF chunk]
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        // [Chunk describing the data format]
+        file.write_all(b"fmt ")?;
+        // format size
+        file.write_all(&16u32.to_le_bytes())?;
+        // format PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&u16::from(num_channels).to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        // bytes per second
+        file.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        // bytes per sample
+        file.write_all(&block_align.to_le_bytes())?;
+        // bits per sample
+        file.write_all(&16u16.to_le_bytes())?;
+
+        // [Chunk containing the sampled data]
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { file, data_size: 0 })
     }
 
-    fs::write("dev/test.wav", wav_file).unwrap();
-    println!("Wrote {} to disk", file_name)
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let size = samples.len() as u32 * 2;
+
+        if u32::MAX - HEADER_SIZE - self.data_size < size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WAV file size limit reached",
+            ));
+        }
+
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+
+        self.data_size += size;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.file
+            .write_all(&(self.data_size + HEADER_SIZE - 8).to_le_bytes())?;
+
+        self.file.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+
+        self.file.flush()
+    }
 }
@@ -5,80 +5,295 @@ extern crate zenith_lib;
 use gameboy::gameboy::EmulatorConfig;
 use zenith_lib::*;
 
+const DEFAULT_RENDER_SECONDS: u64 = 180;
+
 fn main() {
     let enable_saving = true;
     let sync_video = true;
     let sync_audio = true;
     let comp_mode = None;
+    let model = None;
+    let dmg_palette = None;
+    let audio_config = apu::apu::AudioConfig::new(
+        apu::apu::APU_FREQ,
+        apu::apu::APU_SAMPLES_PER_CHANNEL,
+        apu::apu::APU_NUM_CHANNELS,
+        apu::blip::AudioQuality::High,
+        apu::highpass::HighPassFilter::Accurate,
+    );
+
+    let args: Vec<String> = std::env::args().collect();
+
+    // Boot ROM dumps named after the model, e.g. dmg_boot.bin: --boot-rom <dir>
+    let boot_rom_dir = arg_value(&args, "--boot-rom").map(std::path::PathBuf::from);
+
+    // LCD ghosting of the emulated model: --blend
+    let frame_blending = if args.iter().any(|arg| arg == "--blend") {
+        ppu::ppu::FrameBlending::Model
+    } else {
+        ppu::ppu::FrameBlending::Off
+    };
+
+    // Headless GBS rendering: zenith <file.gbs> --wav <out.wav> [--track N] [--seconds S]
+    if let (Some(gbs_path), Some(wav_path)) = (args.get(1), arg_value(&args, "--wav")) {
+        let track = match arg_value(&args, "--track").map(|track| track.parse::<u8>()) {
+            Some(Ok(track)) => Some(track),
+            Some(Err(_)) => {
+                eprintln!("Invalid track number");
+                std::process::exit(1);
+            }
+            None => None,
+        };
+        let seconds = arg_value(&args, "--seconds")
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(DEFAULT_RENDER_SECONDS);
+
+        if let Err(err) = render_gbs(gbs_path, track, seconds, wav_path, audio_config) {
+            eprintln!("Unable to render {}: {}", gbs_path, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let link = connect_link(&args);
 
     let sdl_ctx = sdl2::init().unwrap();
     let mut canvas = sdl2_create_window(&sdl_ctx);
 
-    let (_ad, sound_chan) = sdl2_create_audio(&sdl_ctx);
+    let (_ad, sound_chan, audio) = sdl2_create_audio(&sdl_ctx, audio_config);
 
     let _controller = sdl2_enable_controller(&sdl_ctx);
 
     let (mut frame_send, mut frame_recv) =
         std::sync::mpsc::sync_channel::<ppu::ppu::FrameBuffer>(1);
+    let (mut sgb_frame_send, mut sgb_frame_recv) =
+        std::sync::mpsc::sync_channel::<sgb::screen::SgbFrameBuffer>(1);
+    let (mut viewer_send, mut viewer_recv) =
+        std::sync::mpsc::sync_channel::<Box<ppu::viewer::PpuSnapshot>>(1);
 
     let mut event_pump = sdl_ctx.event_pump().unwrap();
     let mut state = State::Idle;
 
-    if let Some(preload_rom) = std::env::args().nth(1) {
-        state = State::Running(Box::new(run_emulator(
-            &preload_rom,
-            EmulatorConfig {
-                comp_mode,
-                bp_chan: None,
-                sound_chan: Some(sound_chan.clone()),
-                frame_chan: Some(frame_send.clone()),
-                input_recv: None,
-                max_cycles: None,
-                enable_saving,
-                sync_audio,
-                sync_video,
-            },
-        )));
-    }
-
-    'eventloop: loop {
-        let next_state = match state {
-            State::Idle => state_idle(&mut event_pump),
-            State::Running(ref ctx) => {
-                state_running(ctx, &mut canvas, &frame_recv, &mut event_pump, sync_video)
-            }
+    if let Some(preload_rom) = args.get(1).filter(|arg| !arg.starts_with("--")) {
+        let config = EmulatorConfig {
+            comp_mode,
+            model,
+            boot_rom_dir: boot_rom_dir.clone(),
+            dmg_palette,
+            frame_blending,
+            audio,
+            bp_chan: None,
+            sound_chan: Some(sound_chan.clone()),
+            frame_chan: Some(frame_send.clone()),
+            sgb_frame_chan: Some(sgb_frame_send.clone()),
+            viewer_chan: Some(viewer_send.clone()),
+            serial: serial_peripheral(&link),
+            input_recv: None,
+            command_recv: None,
+            max_cycles: None,
+            enable_saving,
+            sync_audio,
+            sync_video,
         };
 
-        match next_state {
-            Some(NextState::LoadRom(rom_path)) => {
-                if let State::Running(ctx) = state {
-                    drop(frame_recv);
-                    _ = ctx.handle.join();
-                    (frame_send, frame_recv) =
-                        std::sync::mpsc::sync_channel::<ppu::ppu::FrameBuffer>(1);
-                }
-
-                state = State::Running(Box::new(run_emulator(
-                    &rom_path,
+        // Two linked Game Boys side by side: zenith <rom> --linked <rom>
+        state = match arg_value(&args, "--linked") {
+            Some(linked_rom) => State::Running(Box::new(run_linked(
+                [preload_rom, linked_rom],
+                [
+                    config,
                     EmulatorConfig {
                         comp_mode,
+                        model,
+                        boot_rom_dir: boot_rom_dir.clone(),
+                        dmg_palette,
+                        frame_blending,
+                        audio,
                         bp_chan: None,
-                        sound_chan: Some(sound_chan.clone()),
-                        frame_chan: Some(frame_send.clone()),
+                        sound_chan: None,
+                        frame_chan: None,
+                        sgb_frame_chan: None,
+                        viewer_chan: None,
+                        serial: serial::peripheral::SerialPeripheral::None,
                         input_recv: None,
+                        command_recv: None,
                         max_cycles: None,
                         enable_saving,
                         sync_audio,
                         sync_video,
                     },
-                )));
-            }
+                ],
+            ))),
+            None => start_emulator(preload_rom, None, config),
+        };
+    }
+
+    'eventloop: loop {
+        let next_state = match state {
+            State::Idle => state_idle(&mut event_pump),
+            State::Running(ref ctx) => state_running(
+                ctx,
+                &mut canvas,
+                &frame_recv,
+                &sgb_frame_recv,
+                &viewer_recv,
+                &mut event_pump,
+                sync_video,
+            ),
+        };
+
+        let load = match next_state {
+            Some(NextState::LoadRom(rom_path)) => Some((rom_path, None)),
+            Some(NextState::LoadGbsTrack(rom_path, track)) => Some((rom_path, Some(track))),
             Some(NextState::Exit) => {
                 break 'eventloop;
             }
             None => {
                 std::thread::sleep(std::time::Duration::from_millis(100));
+                None
+            }
+        };
+
+        if let Some((rom_path, track)) = load {
+            if let State::Running(ctx) = state {
+                drop(frame_recv);
+                drop(sgb_frame_recv);
+                drop(viewer_recv);
+                _ = ctx.handle.join();
+                (frame_send, frame_recv) =
+                    std::sync::mpsc::sync_channel::<ppu::ppu::FrameBuffer>(1);
+                (sgb_frame_send, sgb_frame_recv) =
+                    std::sync::mpsc::sync_channel::<sgb::screen::SgbFrameBuffer>(1);
+                (viewer_send, viewer_recv) =
+                    std::sync::mpsc::sync_channel::<Box<ppu::viewer::PpuSnapshot>>(1);
             }
+
+            state = start_emulator(
+                &rom_path,
+                track,
+                EmulatorConfig {
+                    comp_mode,
+                    model,
+                    boot_rom_dir: boot_rom_dir.clone(),
+                    dmg_palette,
+                    frame_blending,
+                    audio,
+                    bp_chan: None,
+                    sound_chan: Some(sound_chan.clone()),
+                    frame_chan: Some(frame_send.clone()),
+                    sgb_frame_chan: Some(sgb_frame_send.clone()),
+                    viewer_chan: Some(viewer_send.clone()),
+                    serial: serial_peripheral(&link),
+                    input_recv: None,
+                    command_recv: None,
+                    max_cycles: None,
+                    enable_saving,
+                    sync_audio,
+                    sync_video,
+                },
+            );
         }
     }
 }
+
+// GBS files play the given 0-based track, or the file's first song
+fn start_emulator(rom_path: &str, track: Option<u8>, config: EmulatorConfig) -> State {
+    if !gbs::gbs::is_gbs_file(rom_path) {
+        return State::Running(Box::new(run_emulator(rom_path, config)));
+    }
+
+    match gbs::gbs::GbsFile::load(rom_path) {
+        Ok(gbs_file) => {
+            let track = track.unwrap_or(gbs_file.default_track());
+            State::Running(Box::new(run_gbs(&gbs_file, track, config)))
+        }
+        Err(err) => {
+            eprintln!("Unable to load {}: {}", rom_path, err);
+            State::Idle
+        }
+    }
+}
+
+// Track is 1-based like in players
+fn render_gbs(
+    gbs_path: &str,
+    track: Option<u8>,
+    seconds: u64,
+    wav_path: &str,
+    audio: apu::apu::AudioConfig,
+) -> std::io::Result<()> {
+    let gbs_file = gbs::gbs::GbsFile::load(gbs_path)?;
+    let track = match track {
+        Some(0) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "tracks are numbered from 1",
+            ))
+        }
+        Some(track) => track - 1,
+        None => gbs_file.default_track(),
+    };
+
+    gbs::gbs::render_to_wav(
+        &gbs_file,
+        track,
+        seconds,
+        std::path::Path::new(wav_path),
+        audio,
+    )
+}
+
+// Link cable: --host [port] waits for another instance, --join <address[:port]> connects to one.
+// The host only accepts local connections unless --bind <address> picks another interface
+fn connect_link(args: &[String]) -> Option<serial::link::TcpLink> {
+    let link = if args.iter().any(|arg| arg == "--host") {
+        let port = arg_value(args, "--host")
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(serial::link::LINK_DEFAULT_PORT);
+        let bind = match arg_value(args, "--bind").map(|bind| bind.parse()) {
+            Some(Ok(bind)) => bind,
+            Some(Err(_)) => {
+                eprintln!("Invalid bind address");
+                return None;
+            }
+            None => serial::link::LINK_DEFAULT_BIND,
+        };
+
+        println!("Waiting for a link cable connection on {}:{}", bind, port);
+        serial::link::TcpLink::host((bind, port))
+    } else if let Some(address) = arg_value(args, "--join") {
+        if address.contains(':') {
+            serial::link::TcpLink::join(address)
+        } else {
+            serial::link::TcpLink::join((address, serial::link::LINK_DEFAULT_PORT))
+        }
+    } else {
+        return None;
+    };
+
+    match link {
+        Ok(link) => Some(link),
+        Err(err) => {
+            eprintln!("Unable to connect the link cable: {}", err);
+            None
+        }
+    }
+}
+
+// The printer is connected when there's no link cable. The connection stays up when another ROM
+// or GBS track is loaded
+fn serial_peripheral(link: &Option<serial::link::TcpLink>) -> serial::peripheral::SerialPeripheral {
+    match link {
+        Some(link) => {
+            serial::peripheral::SerialPeripheral::Link(serial::link::LinkCable::Tcp(link.share()))
+        }
+        None => serial::peripheral::SerialPeripheral::Printer(std::path::PathBuf::from(".")),
+    }
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
@@ -4,6 +4,8 @@ pub struct Cartridge {
     pub data: Vec<u8>,
     pub header: CartridgeHeader,
     pub rom_path: String,
+    // Synthetic cartridge playing a GBS file
+    pub gbs: bool,
 }
 
 impl Cartridge {
@@ -14,6 +16,7 @@ impl Cartridge {
             data,
             header,
             rom_path: file_path.to_string(),
+            gbs: false,
         }
     }
 }
@@ -94,4 +97,9 @@ impl CartridgeHeader {
     pub fn is_cgb(&self) -> bool {
         self.cgb_flag & 0x80 != 0
     }
+
+    // SGB functions are only enabled with the old licensee code 0x33
+    pub fn is_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.lic_code_old == 0x33
+    }
 }
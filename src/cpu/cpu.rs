@@ -11,7 +11,7 @@ use crate::{
     cartridge::cartridge::Cartridge,
     soc::{interrupt::*, soc::SOC},
     util::*,
-    CompatibilityMode,
+    CompatibilityMode, HardwareModel,
 };
 
 pub type BpSender = SyncSender<(u16, u16, u16)>;
@@ -58,6 +58,7 @@ pub struct CPU {
     pub ime_next: bool,
     pub ime: bool,
     pub halted: bool,
+    pub halt_bug: bool,
 
     pub ld_bb_breakpoint: Option<BpSender>,
 }
@@ -121,78 +122,87 @@ impl CPU {
             ime: false,
             ime_next: false,
             halted: false,
+            halt_bug: false,
             ld_bb_breakpoint,
         }
     }
 
-    pub fn init(&mut self, _soc: &mut SOC, cartridge: &Cartridge, mode: CompatibilityMode) {
-        match mode {
-            CompatibilityMode::ModeCgb => {
-                self.a().set(0x11);
-                self.b().set(0x0);
-                self.c().set(0x0);
-                self.d().set(0xFF);
-                self.e().set(0x56);
-                self.h().set(0x0);
-                self.l().set(0x0D);
-
-                self.sp().set(0xFFFE);
-                self.pc().set(0x100);
-
-                self.set_flag(FLAG_Z, true);
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, false);
+    pub fn init(
+        &mut self,
+        soc: &mut SOC,
+        cartridge: &Cartridge,
+        model: HardwareModel,
+        mode: CompatibilityMode,
+    ) {
+        // The boot ROM sets up the registers itself, starting from 0x0000 with everything cleared
+        if let Some(opcode) = soc.boot_rom_byte(0x0) {
+            self.opcode = opcode;
+            self.pc().set(0x1);
+            return;
+        }
+
+        // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+        // H and C depend on the header checksum on DMG and MGB
+        let checksum_flag = cartridge.header.header_checksum != 0x0;
+        let cgb_mode = mode == CompatibilityMode::ModeCgb;
+
+        // A, B, C, D, E, H, L and the Z, H, C flags
+        let (a, b, c, d, e, h, l, flag_z, flag_h, flag_c) = match model {
+            HardwareModel::Dmg0 => (0x1, 0xFF, 0x13, 0x0, 0xC1, 0x84, 0x03, false, false, false),
+            HardwareModel::Dmg => (
+                0x1,
+                0x0,
+                0x13,
+                0x0,
+                0xD8,
+                0x1,
+                0x4D,
+                true,
+                checksum_flag,
+                checksum_flag,
+            ),
+            HardwareModel::Mgb => (
+                0xFF,
+                0x0,
+                0x13,
+                0x0,
+                0xD8,
+                0x1,
+                0x4D,
+                true,
+                checksum_flag,
+                checksum_flag,
+            ),
+            HardwareModel::Sgb => (0x1, 0x0, 0x14, 0x0, 0x0, 0xC0, 0x60, false, false, false),
+            HardwareModel::Sgb2 => (0xFF, 0x0, 0x14, 0x0, 0x0, 0xC0, 0x60, false, false, false),
+            HardwareModel::Cgb | HardwareModel::CgbE if cgb_mode => {
+                (0x11, 0x0, 0x0, 0xFF, 0x56, 0x0, 0x0D, true, false, false)
             }
-            CompatibilityMode::ModeCgbDmg => {
-                self.a().set(0x11);
-                self.b().set(0x0);
-                self.c().set(0x0);
-                self.d().set(0x0);
-                self.e().set(0x08);
-                self.h().set(0x0);
-                self.l().set(0x7C);
-
-                self.sp().set(0xFFFE);
-                self.pc().set(0x100);
-
-                self.set_flag(FLAG_Z, true);
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, false);
+            HardwareModel::Cgb | HardwareModel::CgbE => {
+                (0x11, 0x0, 0x0, 0x0, 0x08, 0x0, 0x7C, true, false, false)
             }
-            CompatibilityMode::ModeDmg => {
-                self.a().set(0x1);
-                self.b().set(0);
-                self.c().set(0x13);
-                self.d().set(0);
-                self.e().set(0xD8);
-                self.h().set(0x1);
-                self.l().set(0x4D);
-
-                self.sp().set(0xFFFE);
-                self.pc().set(0x100);
-
-                self.set_flag(FLAG_Z, true);
-                self.set_flag(FLAG_N, false);
-                self.set_flag(
-                    FLAG_H,
-                    if cartridge.header.header_checksum == 0x0 {
-                        false
-                    } else {
-                        true
-                    },
-                );
-                self.set_flag(
-                    FLAG_C,
-                    if cartridge.header.header_checksum == 0x0 {
-                        false
-                    } else {
-                        true
-                    },
-                );
+            // The AGB boot ROM ends with an extra INC B, which games check to unlock GBA palettes
+            HardwareModel::Agb if cgb_mode => {
+                (0x11, 0x1, 0x0, 0xFF, 0x56, 0x0, 0x0D, false, false, false)
             }
-        }
+            HardwareModel::Agb => (0x11, 0x1, 0x0, 0x0, 0x08, 0x0, 0x7C, false, false, false),
+        };
+
+        self.a().set(a);
+        self.b().set(b);
+        self.c().set(c);
+        self.d().set(d);
+        self.e().set(e);
+        self.h().set(h);
+        self.l().set(l);
+
+        self.sp().set(0xFFFE);
+        self.pc().set(0x100);
+
+        self.set_flag(FLAG_Z, flag_z);
+        self.set_flag(FLAG_N, false);
+        self.set_flag(FLAG_H, flag_h);
+        self.set_flag(FLAG_C, flag_c);
 
         // Hack: next instruction fetch happens at the end of the previously executed instruction.
         // Prefetch first instruction from cartridge
@@ -202,6 +212,18 @@ impl CPU {
     }
 
     pub fn step(&mut self, soc: &mut SOC) -> u64 {
+        // The CPU is stalled while HDMA transfers data
+        let hdma_cycles = soc.clock_hdma_transfer();
+        if hdma_cycles != 0 {
+            return hdma_cycles;
+        }
+
+        // Nothing runs until the joypad wakes up the CPU or the speed switch completes
+        if soc.is_stopped() {
+            soc.clock();
+            return 1;
+        }
+
         let soc_cycles = soc.cycles;
 
         let is_interrupt_cycle = self.check_interrupts(soc);
@@ -244,6 +266,12 @@ impl CPU {
     }
 
     fn clock_fetch(&mut self, soc: &mut SOC) {
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.opcode = soc.clock_read(self.pc().get());
+            return;
+        }
+
         self.opcode = self.clock_consume_byte_from_pc(soc);
     }
 
@@ -390,13 +418,17 @@ impl CPU {
         }
     }
 
+    // Stack accesses go through these to trigger the OAM bug on every push and pop
     pub fn clock_push_u8(&mut self, soc: &mut SOC, val: u8) {
+        soc.oam_bug_inc_dec(self.sp().get());
         self.sp().dec();
         soc.clock_write(self.sp().get(), val);
     }
 
     pub fn clock_pop_u16(&mut self, soc: &mut SOC) -> u16 {
+        soc.oam_bug_inc_dec(self.sp().get());
         let lsb = soc.clock_read(self.sp().inc());
+        soc.oam_bug_inc_dec(self.sp().get());
         let msb = soc.clock_read(self.sp().inc());
         return util::value(msb, lsb);
     }
@@ -53,6 +53,7 @@ impl cpu::CPU {
 
     pub fn opcode_ld_a_hliaddr(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         debug_assert!(opcode == 0x2A);
+        soc.oam_bug_inc_dec(self.hl().get());
         let val = soc.clock_read(self.hl().get());
         self.a().set(val);
         self.hl().inc();
@@ -60,6 +61,7 @@ impl cpu::CPU {
 
     pub fn opcode_ld_a_hldaddr(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         debug_assert!(opcode == 0x3A);
+        soc.oam_bug_inc_dec(self.hl().get());
         let val = soc.clock_read(self.hl().get());
         self.a().set(val);
         self.hl().dec();
@@ -132,11 +134,13 @@ impl cpu::CPU {
 
     pub fn opcode_ld_hliaddr_a(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         debug_assert!(opcode == 0x22);
+        soc.oam_bug_inc_dec(self.hl().get());
         soc.clock_write(self.hl().inc(), self.a().get());
     }
 
     pub fn opcode_ld_hldaddr_a(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         debug_assert!(opcode == 0x32);
+        soc.oam_bug_inc_dec(self.hl().get());
         soc.clock_write(self.hl().dec(), self.a().get());
     }
 
@@ -231,6 +235,7 @@ impl cpu::CPU {
         let sum = curr_val.wrapping_add(1);
         self.write_r16(dst_reg, sum);
 
+        soc.oam_bug_inc_dec(curr_val);
         soc.clock();
     }
 
@@ -267,6 +272,7 @@ impl cpu::CPU {
         let sum = curr_val.wrapping_sub(1);
         self.write_r16(dst_reg, sum);
 
+        soc.oam_bug_inc_dec(curr_val);
         soc.clock();
     }
 
@@ -373,9 +379,29 @@ impl cpu::CPU {
 
     pub fn opcode_stop(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         debug_assert!(opcode == 0x10);
-        // @todo CGB: Enter CPU very low power mode when in DMG mode (but nobody used it)
+        // https://gbdev.io/pandocs/Reducing_Power_Consumption.html#the-bizarre-case-of-the-game-boy-stop-instruction-before-even-considering-timing
+        let interrupt_pending = soc.active_interrupts() != 0;
 
-        soc.cgb_speed_switch();
+        // STOP is a 2-byte opcode only when no interrupt is pending
+        if !interrupt_pending {
+            self.pc().inc();
+        }
+
+        if soc.joypad_input_low() {
+            // A button is held, STOP turns into HALT or does nothing
+            if !interrupt_pending {
+                self.halted = true;
+                soc.set_cpu_halted(true);
+            }
+            return;
+        }
+
+        if soc.speed_switch_armed() {
+            soc.cgb_speed_switch(!interrupt_pending);
+            return;
+        }
+
+        soc.stop();
     }
 
     pub fn opcode_jr_e8(&mut self, soc: &mut SOC, _instr: &Instruction, _opcode: u8) {
@@ -464,8 +490,9 @@ impl cpu::CPU {
             return;
         }
 
-        // @todo Halt bug
-        // The CPU continues execution after the HALT, but the byte after it is read twice in a row (PC is not incremented, due to a hardware bug).
+        // Halt bug: the CPU continues execution after the HALT, but the byte after it is read
+        // twice in a row (PC is not incremented, due to a hardware bug)
+        self.halt_bug = true;
     }
 
     pub fn opcode_adc(&mut self, soc: &mut SOC, instr: &Instruction, opcode: u8) {
@@ -547,6 +574,7 @@ impl cpu::CPU {
 
     pub fn opcode_pop(&mut self, soc: &mut SOC, _instr: &Instruction, opcode: u8) {
         let reg = (opcode >> 4) & 0x3;
+
         let val = self.clock_pop_u16(soc);
         self.write_r16stk(reg, val);
     }
@@ -0,0 +1,324 @@
+use crate::util::util;
+
+// Post-processing of frames on the CPU: pixel-art scalers and LCD effects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleFilter {
+    None,
+    Scale2x,
+    Scale3x,
+    XbrLite,
+    DmgGrid,
+    CgbSubpixel,
+}
+
+impl ScaleFilter {
+    pub fn scale(&self) -> usize {
+        match self {
+            ScaleFilter::None => 1,
+            ScaleFilter::Scale2x | ScaleFilter::XbrLite => 2,
+            ScaleFilter::Scale3x | ScaleFilter::DmgGrid | ScaleFilter::CgbSubpixel => 3,
+        }
+    }
+
+    // Used to cycle through all filters with a single hotkey
+    pub fn next(&self) -> ScaleFilter {
+        match self {
+            ScaleFilter::None => ScaleFilter::Scale2x,
+            ScaleFilter::Scale2x => ScaleFilter::Scale3x,
+            ScaleFilter::Scale3x => ScaleFilter::XbrLite,
+            ScaleFilter::XbrLite => ScaleFilter::DmgGrid,
+            ScaleFilter::DmgGrid => ScaleFilter::CgbSubpixel,
+            ScaleFilter::CgbSubpixel => ScaleFilter::None,
+        }
+    }
+}
+
+// 24-bit RGB image, 3 bytes per pixel
+pub struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels.resize(width * height * 3, 0);
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let index = (y * self.width + x) * 3;
+        (
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        )
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        let index = (y * self.width + x) * 3;
+        self.pixels[index] = color.0;
+        self.pixels[index + 1] = color.1;
+        self.pixels[index + 2] = color.2;
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+type Yuv = (i32, i32, i32);
+
+// Source image converted to RGB with clamped access at the edges
+struct Source {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Source {
+    fn index(&self, x: usize, y: usize, dx: isize, dy: isize) -> usize {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        y * self.width + x
+    }
+
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+        self.pixels[self.index(x, y, dx, dy)]
+    }
+}
+
+// Applies a filter to frames, the buffers are kept between frames
+pub struct Scaler {
+    src: Source,
+    // Only needed for edge detection
+    yuv: Vec<Yuv>,
+    img: RgbImage,
+}
+
+impl Default for Scaler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scaler {
+    pub fn new() -> Self {
+        Self {
+            src: Source {
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+            },
+            yuv: Vec::new(),
+            img: RgbImage::new(0, 0),
+        }
+    }
+
+    pub fn apply<const W: usize, const H: usize>(
+        &mut self,
+        filter: ScaleFilter,
+        rt: &[[u16; W]; H],
+    ) -> &RgbImage {
+        let Scaler { src, yuv, img } = self;
+
+        let scale = filter.scale();
+        img.resize(W * scale, H * scale);
+
+        // Converted straight into the output
+        if filter == ScaleFilter::None {
+            for (pixel, color) in img.pixels.chunks_exact_mut(3).zip(rt.iter().flatten()) {
+                let (r, g, b) = util::rgb_from_gb_color(*color);
+                pixel.copy_from_slice(&[r, g, b]);
+            }
+            return img;
+        }
+
+        src.width = W;
+        src.height = H;
+        src.pixels.clear();
+        src.pixels.extend(
+            rt.iter()
+                .flat_map(|line| line.iter().map(|color| util::rgb_from_gb_color(*color))),
+        );
+
+        yuv.clear();
+        if filter == ScaleFilter::XbrLite {
+            yuv.extend(src.pixels.iter().map(|color| to_yuv(*color)));
+        }
+
+        // Output pixels for a single source pixel, row by row
+        let mut block = [(0, 0, 0); 9];
+        let block = &mut block[..scale * scale];
+
+        for y in 0..H {
+            for x in 0..W {
+                match filter {
+                    ScaleFilter::None => block[0] = src.get(x, y, 0, 0),
+                    ScaleFilter::Scale2x => scale2x(src, x, y, block),
+                    ScaleFilter::Scale3x => scale3x(src, x, y, block),
+                    ScaleFilter::XbrLite => xbr_lite(src, yuv, x, y, block),
+                    ScaleFilter::DmgGrid => dmg_grid(src, x, y, block),
+                    ScaleFilter::CgbSubpixel => cgb_subpixel(src, x, y, block),
+                }
+
+                for (index, color) in block.iter().enumerate() {
+                    img.set_pixel(x * scale + index % scale, y * scale + index / scale, *color);
+                }
+            }
+        }
+
+        img
+    }
+}
+
+// https://www.scale2x.it/algorithm
+fn scale2x(src: &Source, x: usize, y: usize, out: &mut [Rgb]) {
+    let b = src.get(x, y, 0, -1);
+    let d = src.get(x, y, -1, 0);
+    let e = src.get(x, y, 0, 0);
+    let f = src.get(x, y, 1, 0);
+    let h = src.get(x, y, 0, 1);
+
+    if b == h || d == f {
+        out.fill(e);
+        return;
+    }
+
+    out.copy_from_slice(&[
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]);
+}
+
+fn scale3x(src: &Source, x: usize, y: usize, out: &mut [Rgb]) {
+    let a = src.get(x, y, -1, -1);
+    let b = src.get(x, y, 0, -1);
+    let c = src.get(x, y, 1, -1);
+    let d = src.get(x, y, -1, 0);
+    let e = src.get(x, y, 0, 0);
+    let f = src.get(x, y, 1, 0);
+    let g = src.get(x, y, -1, 1);
+    let h = src.get(x, y, 0, 1);
+    let i = src.get(x, y, 1, 1);
+
+    if b == h || d == f {
+        out.fill(e);
+        return;
+    }
+
+    out.copy_from_slice(&[
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]);
+}
+
+// Simplified 2xBR: each output corner checks which diagonal has the stronger edge
+// and blends towards the neighbour along it
+fn xbr_lite(src: &Source, yuv: &[Yuv], x: usize, y: usize, out: &mut [Rgb]) {
+    let e = src.index(x, y, 0, 0);
+    let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+    for (corner, &(dx, dy)) in out.iter_mut().zip(corners.iter()) {
+        // Neighbours mirrored so that the corner is always at the bottom right
+        let p = |px: isize, py: isize| src.index(x, y, px * dx, py * dy);
+        let d = |a: usize, b: usize| distance(yuv[a], yuv[b]);
+
+        let (b, c, f, g, h, i) = (p(0, -1), p(1, -1), p(1, 0), p(-1, 1), p(0, 1), p(1, 1));
+
+        let edge_hf = d(e, c) + d(e, g) + d(i, p(2, 0)) + d(i, p(0, 2)) + 4 * d(h, f);
+        let edge_ei = d(h, p(-1, 0)) + d(h, p(1, 2)) + d(f, p(2, 1)) + d(f, b) + 4 * d(e, i);
+
+        *corner = if edge_hf < edge_ei {
+            let neighbour = if d(e, f) <= d(e, h) { f } else { h };
+            blend(src.pixels[e], src.pixels[neighbour])
+        } else {
+            src.pixels[e]
+        };
+    }
+}
+
+// Dot matrix with visible gaps between pixels, like the DMG screen
+fn dmg_grid(src: &Source, x: usize, y: usize, out: &mut [Rgb]) {
+    let e = src.get(x, y, 0, 0);
+    let gap = darken(e, 3, 4);
+
+    out.copy_from_slice(&[e, e, gap, e, e, gap, gap, gap, gap]);
+}
+
+// Each pixel is split into red, green and blue columns, like the CGB screen
+fn cgb_subpixel(src: &Source, x: usize, y: usize, out: &mut [Rgb]) {
+    let (r, g, b) = src.get(x, y, 0, 0);
+    let bleed = |value: u8| value / 2;
+
+    let red = (r, bleed(g), bleed(b));
+    let green = (bleed(r), g, bleed(b));
+    let blue = (bleed(r), bleed(g), b);
+
+    out.copy_from_slice(&[
+        red,
+        green,
+        blue,
+        red,
+        green,
+        blue,
+        darken(red, 3, 4),
+        darken(green, 3, 4),
+        darken(blue, 3, 4),
+    ]);
+}
+
+fn darken(color: Rgb, num: u16, den: u16) -> Rgb {
+    let scale = |value: u8| (u16::from(value) * num / den) as u8;
+    (scale(color.0), scale(color.1), scale(color.2))
+}
+
+fn blend(a: Rgb, b: Rgb) -> Rgb {
+    let mix = |a: u8, b: u8| ((u16::from(a) + u16::from(b)) / 2) as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+fn to_yuv((r, g, b): Rgb) -> Yuv {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+    let y = (299 * r + 587 * g + 114 * b) / 1000;
+    (y, (b - y) * 493 / 1000, (r - y) * 877 / 1000)
+}
+
+// Color distance in YUV space, weighted towards luma
+fn distance(a: Yuv, b: Yuv) -> u32 {
+    (48 * (a.0 - b.0).unsigned_abs())
+        + (7 * (a.1 - b.1).unsigned_abs())
+        + (6 * (a.2 - b.2).unsigned_abs())
+}
@@ -1,13 +1,20 @@
 use std::{
     fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use crate::{
-    apu::apu,
+    apu::apu::{self, AudioConfig},
     cartridge::cartridge::*,
     cpu::cpu,
-    ppu::ppu::{self, FrameBuffer},
+    ppu::{
+        ppu::{self, FrameBlending, FrameBuffer},
+        viewer::{PpuSnapshot, PpuViewerSender},
+    },
+    serial::peripheral::SerialPeripheral,
+    sgb::screen::SgbFrameSender,
     soc::soc,
 };
 
@@ -16,6 +23,150 @@ pub enum CompatibilityMode {
     ModeCgb,
     ModeCgbDmg,
     ModeDmg,
+    ModeSgb,
+}
+
+impl CompatibilityMode {
+    pub fn is_cgb_hardware(&self) -> bool {
+        *self == CompatibilityMode::ModeCgb || *self == CompatibilityMode::ModeCgbDmg
+    }
+}
+
+// The console being emulated. Decides the compatibility mode together with the cartridge
+// and the state the boot ROM leaves behind, which games use to detect the hardware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HardwareModel {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    CgbE,
+    Agb,
+}
+
+impl HardwareModel {
+    // Default model for a forced compatibility mode
+    pub fn from_comp_mode(comp_mode: CompatibilityMode) -> HardwareModel {
+        match comp_mode {
+            CompatibilityMode::ModeDmg => HardwareModel::Dmg,
+            CompatibilityMode::ModeSgb => HardwareModel::Sgb,
+            CompatibilityMode::ModeCgb | CompatibilityMode::ModeCgbDmg => HardwareModel::Cgb,
+        }
+    }
+
+    pub fn comp_mode(&self, header: &CartridgeHeader) -> CompatibilityMode {
+        match self {
+            HardwareModel::Dmg0 | HardwareModel::Dmg | HardwareModel::Mgb => {
+                CompatibilityMode::ModeDmg
+            }
+            HardwareModel::Sgb | HardwareModel::Sgb2 => CompatibilityMode::ModeSgb,
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => {
+                if header.is_cgb() {
+                    CompatibilityMode::ModeCgb
+                } else {
+                    CompatibilityMode::ModeCgbDmg
+                }
+            }
+        }
+    }
+
+    // Forcing a compatibility mode only works with the hardware behind it
+    pub fn supports(&self, comp_mode: CompatibilityMode) -> bool {
+        match self {
+            HardwareModel::Dmg0 | HardwareModel::Dmg | HardwareModel::Mgb => {
+                comp_mode == CompatibilityMode::ModeDmg
+            }
+            HardwareModel::Sgb | HardwareModel::Sgb2 => {
+                comp_mode == CompatibilityMode::ModeSgb || comp_mode == CompatibilityMode::ModeDmg
+            }
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => {
+                comp_mode.is_cgb_hardware()
+            }
+        }
+    }
+
+    // Internal 16-bit divider when the boot ROM hands over to the cartridge at 0x100.
+    // The CGB boot ROM takes longer for DMG games as it also sets up the compatibility palettes
+    pub fn div_seed(&self, comp_mode: CompatibilityMode) -> Option<u16> {
+        match self {
+            HardwareModel::Dmg0 => Some(0x1830),
+            HardwareModel::Dmg | HardwareModel::Mgb => Some(0xABCC),
+            // The SGB boot ROM hands over later as it also sends the header to the SNES,
+            // Pan Docs lists the DIV as unknown
+            HardwareModel::Sgb | HardwareModel::Sgb2 => None,
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => {
+                if comp_mode == CompatibilityMode::ModeCgb {
+                    Some(0x1EA0)
+                } else {
+                    Some(0x267C)
+                }
+            }
+        }
+    }
+
+    // Boot ROM dump of the model, named like in most emulators. CGB revisions A to E share one
+    pub fn boot_rom_file(&self) -> &'static str {
+        match self {
+            HardwareModel::Dmg0 => "dmg0_boot.bin",
+            HardwareModel::Dmg => "dmg_boot.bin",
+            HardwareModel::Mgb => "mgb_boot.bin",
+            HardwareModel::Sgb => "sgb_boot.bin",
+            HardwareModel::Sgb2 => "sgb2_boot.bin",
+            HardwareModel::Cgb | HardwareModel::CgbE => "cgb_boot.bin",
+            HardwareModel::Agb => "agb_boot.bin",
+        }
+    }
+
+    // 0x0000-0x00FF, CGB boot ROMs continue at 0x0200-0x08FF
+    pub fn boot_rom_size(&self) -> usize {
+        match self {
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => 0x900,
+            _ => 0x100,
+        }
+    }
+
+    // Missing or truncated dumps fall back to the registers the boot ROM leaves behind
+    pub fn load_boot_rom(&self, dir: &Path) -> Option<Vec<u8>> {
+        let path = dir.join(self.boot_rom_file());
+
+        match fs::read(&path) {
+            Ok(boot_rom) if boot_rom.len() == self.boot_rom_size() => Some(boot_rom),
+            Ok(boot_rom) => {
+                eprintln!(
+                    "Ignoring {}: {} bytes instead of {}",
+                    path.display(),
+                    boot_rom.len(),
+                    self.boot_rom_size()
+                );
+                None
+            }
+            Err(err) => {
+                eprintln!("Unable to load {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+// Palettes used for DMG games. Manual palettes are named after the button
+// combination that selects them during the CGB boot logo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DmgPalette {
+    Classic,
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -39,11 +190,38 @@ pub struct InputEvent {
 pub type InputReceiver = std::sync::mpsc::Receiver<InputEvent>;
 pub type InputSender = std::sync::mpsc::SyncSender<InputEvent>;
 
+// Runtime controls sent by the frontend, applied once per frame
+pub enum EmulatorCommand {
+    // Send a PpuSnapshot through the viewer channel every frame
+    EnablePpuViewer(bool),
+    SetPpuDebugFlags(ppu::PpuDebugFlags),
+    SetChannelMuted(apu::ApuChannel, bool),
+    SetChannelSoloed(apu::ApuChannel, bool),
+    // Record the sound output to a WAV file, errors are printed
+    StartRecording(std::path::PathBuf),
+    StopRecording,
+    // Log the APU register writes to a VGM file, errors are printed
+    StartVgmLog(std::path::PathBuf),
+    StopVgmLog,
+    // Capture a channel on its own to a mono WAV file, errors are printed
+    StartChannelCapture(apu::ApuChannel, std::path::PathBuf),
+    StopChannelCapture(apu::ApuChannel),
+}
+
+pub type CommandReceiver = std::sync::mpsc::Receiver<EmulatorCommand>;
+pub type CommandSender = std::sync::mpsc::SyncSender<EmulatorCommand>;
+
 pub struct EmulatorConfig {
     pub sound_chan: Option<apu::ApuSoundSender>,
     pub frame_chan: Option<ppu::PpuFrameSender>,
+    // Super Game Boy frames (256x224) including the border
+    pub sgb_frame_chan: Option<SgbFrameSender>,
     pub bp_chan: Option<cpu::BpSender>,
     pub input_recv: Option<InputReceiver>,
+    pub command_recv: Option<CommandReceiver>,
+    pub viewer_chan: Option<PpuViewerSender>,
+    // Device plugged into the serial port
+    pub serial: SerialPeripheral,
 
     pub enable_saving: bool,
     pub sync_audio: bool,
@@ -51,11 +229,18 @@ pub struct EmulatorConfig {
     pub max_cycles: Option<u64>,
 
     pub comp_mode: Option<CompatibilityMode>,
+    pub model: Option<HardwareModel>,
+    // Directory with boot ROM dumps, the model's one runs before the cartridge when present
+    pub boot_rom_dir: Option<PathBuf>,
+    pub dmg_palette: Option<DmgPalette>,
+    pub frame_blending: FrameBlending,
+    pub audio: AudioConfig,
 }
 
 pub struct GbCtx {
     pub cgb: bool,
     pub comp_mode: CompatibilityMode,
+    pub model: HardwareModel,
     pub rom_path: String,
 }
 
@@ -74,11 +259,19 @@ impl Display for Gameboy {
 }
 
 impl Gameboy {
-    pub fn new(cartridge: Cartridge, config: Box<EmulatorConfig>) -> Gameboy {
+    pub fn new(cartridge: Cartridge, mut config: Box<EmulatorConfig>) -> Gameboy {
+        if let (Some(model), Some(mode)) = (config.model, config.comp_mode) {
+            assert!(model.supports(mode), "{model:?} can't run in {mode:?}");
+        }
+
         let comp_mode = if let Some(mode) = config.comp_mode {
             mode
+        } else if let Some(model) = config.model {
+            model.comp_mode(&cartridge.header)
         } else if cartridge.header.is_cgb() {
             CompatibilityMode::ModeCgb
+        } else if cartridge.header.is_sgb() {
+            CompatibilityMode::ModeSgb
         } else {
             CompatibilityMode::ModeCgbDmg
         };
@@ -87,21 +280,19 @@ impl Gameboy {
             cgb: comp_mode == CompatibilityMode::ModeCgb,
             rom_path: cartridge.rom_path.clone(),
             comp_mode,
+            model: config
+                .model
+                .unwrap_or(HardwareModel::from_comp_mode(comp_mode)),
         });
 
+        let boot_rom = config
+            .boot_rom_dir
+            .as_ref()
+            .and_then(|dir| ctx.model.load_boot_rom(dir));
+
         let gb = Self {
-            soc: soc::SOC::new(
-                &cartridge,
-                config.input_recv,
-                config.sound_chan,
-                config.frame_chan,
-                config.enable_saving,
-                config.sync_audio,
-                config.sync_video,
-                config.max_cycles,
-                ctx.clone(),
-            ),
-            cpu: cpu::CPU::new(config.bp_chan),
+            cpu: cpu::CPU::new(config.bp_chan.take()),
+            soc: soc::SOC::new(&cartridge, *config, boot_rom, ctx.clone()),
             cartridge,
             ctx,
         };
@@ -118,9 +309,36 @@ impl Gameboy {
         }
     }
 
+    // Runs for at least the given M-cycles, true once the emulator has exited
+    pub fn run_cycles(&mut self, cycles: u64) -> bool {
+        let end = self.soc.cycles + cycles;
+
+        while self.soc.cycles < end {
+            let _cycles = self.cpu.step(&mut self.soc);
+            if self.soc.process_events() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // M-cycles per bit at the selected internal serial clock
+    pub fn serial_bit_cycles(&self) -> u64 {
+        self.soc.get_serial().bit_cycles()
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.soc.is_double_speed()
+    }
+
     pub fn boot(&mut self) {
-        self.cpu
-            .init(&mut self.soc, &self.cartridge, self.ctx.comp_mode);
+        self.cpu.init(
+            &mut self.soc,
+            &self.cartridge,
+            self.ctx.model,
+            self.ctx.comp_mode,
+        );
     }
 
     pub fn close(&mut self) {
@@ -135,6 +353,88 @@ impl Gameboy {
         self.soc.get_framebuffer()
     }
 
+    pub fn get_ppu(&self) -> &ppu::PPU {
+        self.soc.get_ppu()
+    }
+
+    pub fn get_ppu_snapshot(&self) -> PpuSnapshot {
+        self.soc.get_ppu().snapshot()
+    }
+
+    pub fn get_ppu_debug_flags(&self) -> ppu::PpuDebugFlags {
+        self.soc.get_ppu().get_debug_flags()
+    }
+
+    pub fn set_ppu_debug_flags(&mut self, debug_flags: ppu::PpuDebugFlags) {
+        self.soc.set_ppu_debug_flags(debug_flags);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: apu::ApuChannel, muted: bool) {
+        self.soc.get_apu_mut().set_channel_muted(channel, muted);
+    }
+
+    pub fn is_channel_muted(&self, channel: apu::ApuChannel) -> bool {
+        self.soc.get_apu().is_channel_muted(channel)
+    }
+
+    pub fn set_channel_soloed(&mut self, channel: apu::ApuChannel, soloed: bool) {
+        self.soc.get_apu_mut().set_channel_soloed(channel, soloed);
+    }
+
+    pub fn is_channel_soloed(&self, channel: apu::ApuChannel) -> bool {
+        self.soc.get_apu().is_channel_soloed(channel)
+    }
+
+    pub fn start_channel_capture(&mut self, channel: apu::ApuChannel) {
+        self.soc.get_apu_mut().start_channel_capture(channel);
+    }
+
+    pub fn start_channel_capture_file(
+        &mut self,
+        channel: apu::ApuChannel,
+        path: &std::path::Path,
+    ) -> std::io::Result<()> {
+        self.soc
+            .get_apu_mut()
+            .start_channel_capture_file(channel, path)
+    }
+
+    pub fn stop_channel_capture(&mut self, channel: apu::ApuChannel) -> std::io::Result<Vec<i16>> {
+        self.soc.get_apu_mut().stop_channel_capture(channel)
+    }
+
+    // Samples captured since the last call, mono at the output sample rate
+    pub fn take_channel_capture(&mut self, channel: apu::ApuChannel) -> Vec<i16> {
+        self.soc.get_apu_mut().take_channel_capture(channel)
+    }
+
+    // Streams the sound output to a WAV file until stop_recording
+    pub fn start_recording(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.soc.get_apu_mut().start_recording(path)
+    }
+
+    // Also reports write errors which happened while recording
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        self.soc.get_apu_mut().stop_recording()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.soc.get_apu().is_recording()
+    }
+
+    // Logs the APU register writes to a VGM file until stop_vgm_log
+    pub fn start_vgm_log(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.soc.get_apu_mut().start_vgm_log(path)
+    }
+
+    pub fn stop_vgm_log(&mut self) -> std::io::Result<()> {
+        self.soc.get_apu_mut().stop_vgm_log()
+    }
+
+    pub fn is_logging_vgm(&self) -> bool {
+        self.soc.get_apu().is_logging_vgm()
+    }
+
     #[cfg(test)]
     pub fn get_cpu(&mut self) -> &mut cpu::CPU {
         &mut self.cpu
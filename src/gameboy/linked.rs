@@ -0,0 +1,122 @@
+use std::sync::mpsc::Receiver;
+
+use crate::{
+    cartridge::cartridge::Cartridge,
+    ppu::ppu::FrameBuffer,
+    serial::{
+        link::{DirectLink, LinkCable},
+        peripheral::SerialPeripheral,
+    },
+};
+
+use super::gameboy::{EmulatorConfig, Gameboy};
+
+// Cycles below are normal speed M-cycles, a Game Boy in double speed runs twice as many
+const FRAME_CYCLES: u64 = 154 * 114;
+// Gives up on a frame after this long, the LCD may be off
+const MAX_RUN_CYCLES: u64 = FRAME_CYCLES * 2;
+
+// Two Game Boys connected with a link cable and stepped in turns in the same thread, which makes
+// multiplayer runs deterministic. The frame channels and serial peripherals of the configs are
+// replaced, frames are returned by run_frame instead
+pub struct LinkedGameboys {
+    gameboys: [Gameboy; 2],
+    frame_recv: [Receiver<FrameBuffer>; 2],
+    frames: [Box<FrameBuffer>; 2],
+}
+
+impl LinkedGameboys {
+    pub fn new(cartridges: [Cartridge; 2], configs: [EmulatorConfig; 2]) -> Self {
+        let (link_a, link_b) = DirectLink::pair();
+        let [cart_a, cart_b] = cartridges;
+        let [config_a, config_b] = configs;
+
+        let (gb_a, recv_a) = LinkedGameboys::create(cart_a, config_a, link_a);
+        let (gb_b, recv_b) = LinkedGameboys::create(cart_b, config_b, link_b);
+
+        Self {
+            gameboys: [gb_a, gb_b],
+            frame_recv: [recv_a, recv_b],
+            frames: [Box::new([[0; 160]; 144]), Box::new([[0; 160]; 144])],
+        }
+    }
+
+    fn create(
+        cartridge: Cartridge,
+        mut config: EmulatorConfig,
+        link: DirectLink,
+    ) -> (Gameboy, Receiver<FrameBuffer>) {
+        // Drained after every slice, a frame is never waited for
+        let (frame_send, frame_recv) = std::sync::mpsc::sync_channel::<FrameBuffer>(1);
+
+        config.frame_chan = Some(frame_send);
+        config.serial = SerialPeripheral::Link(LinkCable::Direct(link));
+
+        (Gameboy::new(cartridge, Box::new(config)), frame_recv)
+    }
+
+    pub fn boot(&mut self) {
+        for gb in &mut self.gameboys {
+            gb.boot();
+        }
+    }
+
+    // Runs until both Game Boys have finished a frame, or for MAX_RUN_CYCLES in which case the
+    // last frame is returned for a Game Boy without a new one. None once either has exited
+    pub fn run_frame(&mut self) -> Option<[&FrameBuffer; 2]> {
+        let mut new_frame = [false; 2];
+        let mut elapsed = 0;
+
+        while !new_frame.iter().all(|new_frame| *new_frame) && elapsed < MAX_RUN_CYCLES {
+            let slice = self.slice_cycles();
+
+            for (i, gb) in self.gameboys.iter_mut().enumerate() {
+                let speed = if gb.is_double_speed() { 2 } else { 1 };
+
+                if gb.run_cycles(slice * speed) {
+                    // The exited one has already closed itself
+                    self.gameboys[i ^ 1].close();
+                    return None;
+                }
+
+                if let Ok(frame) = self.frame_recv[i].try_recv() {
+                    *self.frames[i] = frame;
+                    new_frame[i] = true;
+                }
+            }
+
+            elapsed += slice;
+        }
+
+        Some(self.get_frames())
+    }
+
+    // Each Game Boy runs for one bit of the fastest selected serial clock before the other one's
+    // turn: 128 M-cycles at 8192 Hz, down to 2 with the CGB fast clock in double speed
+    fn slice_cycles(&self) -> u64 {
+        self.gameboys
+            .iter()
+            .map(|gb| {
+                let speed = if gb.is_double_speed() { 2 } else { 1 };
+                gb.serial_bit_cycles() / speed
+            })
+            .min()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    // Last finished frames
+    pub fn get_frames(&self) -> [&FrameBuffer; 2] {
+        [&self.frames[0], &self.frames[1]]
+    }
+
+    pub fn get_gameboy_mut(&mut self, index: usize) -> &mut Gameboy {
+        &mut self.gameboys[index]
+    }
+
+    pub fn close(&mut self) {
+        for gb in &mut self.gameboys {
+            gb.close();
+        }
+    }
+}
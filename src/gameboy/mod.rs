@@ -1 +1,2 @@
 pub mod gameboy;
+pub mod linked;
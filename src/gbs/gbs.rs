@@ -0,0 +1,287 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    apu::apu::AudioConfig,
+    cartridge::cartridge::{Cartridge, CartridgeHeader},
+    gameboy::gameboy::{EmulatorConfig, Gameboy},
+    ppu::ppu::FrameBlending,
+    serial::peripheral::SerialPeripheral,
+};
+
+const GBS_HEADER_SIZE: usize = 0x70;
+
+// The synthetic ROM below the load address: RST and interrupt vectors, cartridge header
+// and the driver which calls INIT and then waits for interrupts to call PLAY
+const DRIVER_ADDR: u16 = 0x150;
+const MIN_LOAD_ADDR: u16 = 0x200;
+
+// MBC5 with RAM, GBS files switch banks by writing to 2000-3FFF and may use A000-BFFF.
+// Selecting bank 0 maps bank 1 as on MBC1, which the rips are written for
+const CART_TYPE: u8 = 0x1A;
+const RAM_SIZE: u8 = 0x02;
+
+const M_CYCLES_PER_SECOND: u64 = 1_048_576;
+
+// GBS (Game Boy Sound) file header, https://ocremix.org/info/GBS_Format_Specification
+#[derive(Debug, Clone)]
+pub struct GbsHeader {
+    pub num_songs: u8,
+    // 1-based
+    pub first_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    // Bit 2 set: PLAY is called by the timer, otherwise by VBlank. Bit 7: CGB double speed
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+}
+
+pub struct GbsFile {
+    pub header: GbsHeader,
+    pub path: String,
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    pub fn load(path: &str) -> io::Result<GbsFile> {
+        GbsFile::parse(path, &fs::read(path)?)
+    }
+
+    pub fn parse(path: &str, file: &[u8]) -> io::Result<GbsFile> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        if file.len() <= GBS_HEADER_SIZE || &file[0..3] != b"GBS" {
+            return Err(invalid("not a GBS file"));
+        }
+        if file[3] != 1 {
+            return Err(invalid("unsupported GBS version"));
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &file[offset..offset + 32];
+            let len = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).into_owned()
+        };
+
+        let header = GbsHeader {
+            num_songs: file[0x04],
+            first_song: file[0x05],
+            load_addr: word(0x06),
+            init_addr: word(0x08),
+            play_addr: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: file[0x0E],
+            timer_control: file[0x0F],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+        };
+
+        if header.num_songs == 0 {
+            return Err(invalid("GBS file without songs"));
+        }
+        if !(MIN_LOAD_ADDR..0x8000).contains(&header.load_addr) {
+            return Err(invalid("GBS load address out of range"));
+        }
+
+        Ok(GbsFile {
+            header,
+            path: path.to_string(),
+            data: file[GBS_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    // 0-based track played when none is selected
+    pub fn default_track(&self) -> u8 {
+        self.header.first_song.saturating_sub(1) % self.header.num_songs
+    }
+
+    // ROM image with the music data at the load address, which plays the 0-based track
+    pub fn cartridge(&self, track: u8) -> Cartridge {
+        let hdr = &self.header;
+        let load_addr = usize::from(hdr.load_addr);
+
+        // 32 KiB << rom_size
+        let mut rom_size = 0;
+        while (0x8000 << rom_size) < load_addr + self.data.len() {
+            rom_size += 1;
+        }
+
+        let mut rom = vec![0xFF; 0x8000 << rom_size];
+        rom[load_addr..load_addr + self.data.len()].copy_from_slice(&self.data);
+
+        // RST vectors jump to the same offset from the load address
+        for rst in (0x00..=0x38).step_by(8) {
+            let [lo, hi] = (hdr.load_addr + rst).to_le_bytes();
+            rom[usize::from(rst)..usize::from(rst) + 3].copy_from_slice(&[0xC3, lo, hi]);
+        }
+
+        // Interrupt vectors: CALL PLAY; RETI for the selected source, RETI otherwise
+        let [play_lo, play_hi] = hdr.play_addr.to_le_bytes();
+        let play_vector = if hdr.uses_timer() { 0x50 } else { 0x40 };
+        for vector in [0x40, 0x48, 0x50, 0x58, 0x60] {
+            if vector == play_vector {
+                rom[vector..vector + 4].copy_from_slice(&[0xCD, play_lo, play_hi, 0xD9]);
+            } else {
+                rom[vector] = 0xD9;
+            }
+        }
+
+        // Entry point: NOP; JP DRIVER_ADDR
+        let [driver_lo, driver_hi] = DRIVER_ADDR.to_le_bytes();
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, driver_lo, driver_hi]);
+
+        rom[0x104..0x150].fill(0);
+        for (dst, src) in rom[0x134..0x143].iter_mut().zip(hdr.title.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        rom[0x143] = if hdr.double_speed() { 0x80 } else { 0x00 };
+        rom[0x147] = CART_TYPE;
+        rom[0x148] = rom_size;
+        rom[0x149] = RAM_SIZE;
+
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        });
+
+        let driver = self.driver(track);
+        let driver_addr = usize::from(DRIVER_ADDR);
+        rom[driver_addr..driver_addr + driver.len()].copy_from_slice(&driver);
+
+        Cartridge {
+            header: CartridgeHeader::new(&rom),
+            data: rom,
+            rom_path: self.path.clone(),
+            gbs: true,
+        }
+    }
+
+    fn driver(&self, track: u8) -> Vec<u8> {
+        let hdr = &self.header;
+        let [sp_lo, sp_hi] = hdr.stack_pointer.to_le_bytes();
+        let [init_lo, init_hi] = hdr.init_addr.to_le_bytes();
+        let interrupt_enable = if hdr.uses_timer() { 0x04 } else { 0x01 };
+
+        let mut driver = vec![
+            0xF3, // DI
+            0x31, sp_lo, sp_hi, // LD SP, stack pointer
+            0x3E, 0x0A, 0xEA, 0x00, 0x00, // Enable cartridge RAM
+        ];
+
+        if hdr.double_speed() {
+            // LD A, 1; LDH [KEY1], A; STOP
+            driver.extend_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00]);
+        }
+
+        driver.extend_from_slice(&[
+            0x3E,
+            0x80,
+            0xE0,
+            0x40, // LCD on for VBlank, nothing displayed
+            0x3E,
+            hdr.timer_modulo,
+            0xE0,
+            0x06, // TMA
+            0x3E,
+            hdr.timer_control & 0x07,
+            0xE0,
+            0x07, // TAC
+            0x3E,
+            interrupt_enable,
+            0xE0,
+            0xFF, // IE
+            0xAF,
+            0xE0,
+            0x0F, // Clear IF
+            0x3E,
+            track, // LD A, track
+            0xCD,
+            init_lo,
+            init_hi, // CALL INIT
+            0xFB,    // EI
+            0x76,
+            0x00,
+            0x18,
+            0xFC, // HALT; NOP; JR -4
+        ]);
+
+        driver
+    }
+}
+
+pub fn is_gbs_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("gbs"))
+        .unwrap_or(false)
+}
+
+// Headless playback of a track for the given time, the audio is written to wav_path
+pub fn render_to_wav(
+    gbs: &GbsFile,
+    track: u8,
+    seconds: u64,
+    wav_path: &Path,
+    audio: AudioConfig,
+) -> io::Result<()> {
+    if track >= gbs.header.num_songs {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "track {} out of range, the file has {} songs",
+                track + 1,
+                gbs.header.num_songs
+            ),
+        ));
+    }
+
+    let speed = if gbs.header.double_speed() { 2 } else { 1 };
+
+    let mut gb = Gameboy::new(
+        gbs.cartridge(track),
+        Box::new(EmulatorConfig {
+            bp_chan: None,
+            sound_chan: None,
+            frame_chan: None,
+            sgb_frame_chan: None,
+            viewer_chan: None,
+            serial: SerialPeripheral::None,
+            input_recv: None,
+            command_recv: None,
+            enable_saving: false,
+            sync_audio: false,
+            sync_video: false,
+            max_cycles: Some(seconds * M_CYCLES_PER_SECOND * speed),
+            comp_mode: None,
+            model: None,
+            boot_rom_dir: None,
+            dmg_palette: None,
+            frame_blending: FrameBlending::Off,
+            audio,
+        }),
+    );
+
+    gb.boot();
+    gb.start_recording(wav_path)?;
+    gb.run();
+    gb.stop_recording()
+}
@@ -1,22 +1,27 @@
-use std::{
-    path::Path,
-    sync::mpsc::{self},
-    time,
-};
+use std::{path::Path, time};
 
-use apu::apu::{APU_FREQ, APU_NUM_CHANNELS, APU_SAMPLES, APU_SAMPLES_PER_CHANNEL};
+use apu::{
+    apu::{ApuChannel, AudioConfig},
+    ring_buffer::AudioRing,
+};
 use cartridge::cartridge::Cartridge;
-use gameboy::gameboy::*;
-use ppu::ppu::FrameBuffer;
-use sdl2::audio::AudioFormatNum;
+use filter::filter::{RgbImage, ScaleFilter, Scaler};
+use gameboy::{gameboy::*, linked::LinkedGameboys};
+use gbs::gbs::GbsFile;
+use ppu::{ppu::FrameBuffer, viewer::PpuSnapshot};
+use sgb::screen::{SgbFrameBuffer, SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+use util::util::rgb_from_gb_color;
 
 pub mod apu;
 pub mod cartridge;
 pub mod cpu;
+pub mod filter;
 pub mod gameboy;
+pub mod gbs;
 pub mod mbc;
 pub mod ppu;
 pub mod serial;
+pub mod sgb;
 pub mod soc;
 pub mod timer;
 pub mod util;
@@ -24,9 +29,27 @@ pub mod util;
 pub struct EmulatorContext {
     pub handle: std::thread::JoinHandle<()>,
     pub input_send: InputSender,
+    pub command_send: CommandSender,
+    pub rom_filename: String,
+    pub gbs: Option<GbsTrack>,
+    pub linked: Option<LinkedContext>,
+}
+
+// Second Game Boy when two linked ones run side by side
+pub struct LinkedContext {
+    pub input_send: InputSender,
+    pub frame_recv: std::sync::mpsc::Receiver<FrameBuffer>,
     pub rom_filename: String,
 }
 
+// Track being played when the emulator runs a GBS file
+pub struct GbsTrack {
+    pub path: String,
+    // 0-based
+    pub track: u8,
+    pub num_songs: u8,
+}
+
 pub enum State {
     Idle,
     Running(Box<EmulatorContext>),
@@ -35,6 +58,8 @@ pub enum State {
 pub enum NextState {
     Exit,
     LoadRom(String),
+    // Path and 0-based track of a GBS file
+    LoadGbsTrack(String, u8),
 }
 
 pub const GB_DEFAULT_FPS: f64 = 59.73;
@@ -43,10 +68,21 @@ pub const TARGET_FPS: f64 = GB_DEFAULT_FPS;
 const T_CYCLES_PER_SECOND: u64 = 4_194_304;
 const FRAME_TIME: u64 = ((1.0 / TARGET_FPS) * 1000_000.0) as u64;
 
+// Device buffers held by the audio ring, the emulator keeps it about half full
+const AUDIO_RING_BUFFERS: usize = 4;
+
+// Largest difference between display and Game Boy refresh rate that audio rate control can
+// make up for, above it frames are paced with a timer instead of the display
+const HOST_VSYNC_TOLERANCE: f64 = 0.0075;
+
 const GB_SCREEN_WIDTH: u32 = 160;
 const GB_SCREEN_HEIGHT: u32 = 144;
 const WINDOW_SIZE_MULT: u32 = 4;
 
+// Tilemaps, tiles and palettes on top, sprites below
+const VIEWER_WIDTH: u32 = 256 * 3;
+const VIEWER_HEIGHT: u32 = 256 + 80;
+
 pub fn sdl2_create_window(sdl_ctx: &sdl2::Sdl) -> sdl2::render::Canvas<sdl2::video::Window> {
     let video_subsystem = sdl_ctx.video().unwrap();
 
@@ -62,65 +98,104 @@ pub fn sdl2_create_window(sdl_ctx: &sdl2::Sdl) -> sdl2::render::Canvas<sdl2::vid
         .build()
         .expect("could not create window");
 
+    let display_hz = video_subsystem
+        .current_display_mode(0)
+        .map(|mode| mode.refresh_rate)
+        .unwrap_or(0);
+    let host_vsync = (f64::from(display_hz) / GB_DEFAULT_FPS - 1.0).abs() < HOST_VSYNC_TOLERANCE;
+
+    let canvas_builder = window.into_canvas().accelerated();
+    let canvas_builder = if host_vsync {
+        canvas_builder.present_vsync()
+    } else {
+        canvas_builder
+    };
+
+    let mut canvas = canvas_builder.build().expect("could not create canvas");
+
+    canvas
+        .set_logical_size(GB_SCREEN_HEIGHT, GB_SCREEN_WIDTH)
+        .expect("canvast must set device independent resolution");
+
+    return canvas;
+}
+
+pub fn sdl2_create_viewer_window(
+    video_subsystem: &sdl2::VideoSubsystem,
+) -> sdl2::render::Canvas<sdl2::video::Window> {
+    let window = video_subsystem
+        .window("Zenith - PPU viewer", VIEWER_WIDTH * 2, VIEWER_HEIGHT * 2)
+        .resizable()
+        .opengl()
+        .build()
+        .expect("could not create viewer window");
+
     let mut canvas = window
         .into_canvas()
         .accelerated()
         .build()
-        .expect("could not create canvas");
+        .expect("could not create viewer canvas");
 
     canvas
-        .set_logical_size(GB_SCREEN_HEIGHT, GB_SCREEN_WIDTH)
+        .set_logical_size(VIEWER_WIDTH, VIEWER_HEIGHT)
         .expect("canvast must set device independent resolution");
 
-    return canvas;
+    canvas
 }
 
 pub struct GbAudio {
-    sound_recv: mpsc::Receiver<Vec<i16>>,
+    ring: apu::apu::ApuSoundSender,
 }
 
 impl sdl2::audio::AudioCallback for GbAudio {
     type Channel = i16;
 
     fn callback(&mut self, out: &mut [Self::Channel]) {
-        match self
-            .sound_recv
-            .recv_timeout(time::Duration::from_millis(15))
-        {
-            Ok(samples) => {
-                debug_assert!(samples.len() == out.len());
-
-                out.copy_from_slice(&samples);
-            }
-            Err(_err) => {
-                for i in 0..APU_SAMPLES {
-                    out[i] = Self::Channel::SILENCE;
-                }
-            }
-        }
+        self.ring.pop(out);
     }
 }
 
+// Returns the format the device was opened with, it can differ from the desired one
 pub fn sdl2_create_audio(
     sdl_ctx: &sdl2::Sdl,
-) -> (sdl2::audio::AudioDevice<GbAudio>, apu::apu::ApuSoundSender) {
+    audio_config: AudioConfig,
+) -> (
+    sdl2::audio::AudioDevice<GbAudio>,
+    apu::apu::ApuSoundSender,
+    AudioConfig,
+) {
     let audio_subsystem = sdl_ctx.audio().unwrap();
 
     let spec_desired = sdl2::audio::AudioSpecDesired {
-        channels: Some(APU_NUM_CHANNELS),
-        samples: Some(APU_SAMPLES_PER_CHANNEL),
-        freq: Some(APU_FREQ as i32),
+        channels: Some(audio_config.num_channels),
+        samples: Some(audio_config.buffer_size),
+        freq: Some(audio_config.sample_rate as i32),
     };
 
-    let (sound_send, sound_recv) = mpsc::sync_channel::<Vec<i16>>(1);
-
-    let device = audio_subsystem
-        .open_playback(None, &spec_desired, |_spec| GbAudio { sound_recv })
+    let mut device = audio_subsystem
+        .open_playback(None, &spec_desired, |spec| {
+            // Sized for the obtained format
+            let ring = std::sync::Arc::new(AudioRing::new(
+                usize::from(spec.samples) * usize::from(spec.channels) * AUDIO_RING_BUFFERS,
+                spec.channels,
+            ));
+            GbAudio { ring }
+        })
         .unwrap();
 
     device.resume();
 
-    (device, sound_send)
+    let ring = device.lock().ring.clone();
+    let spec = device.spec();
+    let obtained_config = AudioConfig {
+        sample_rate: spec.freq as u32,
+        buffer_size: spec.samples,
+        num_channels: spec.channels,
+        quality: audio_config.quality,
+        high_pass: audio_config.high_pass,
+    };
+
+    (device, ring, obtained_config)
 }
 
 pub fn sdl2_enable_controller(
@@ -151,30 +226,132 @@ pub fn sdl2_enable_controller(
     return Ok(controller);
 }
 
-pub fn run_emulator(rom_path: &str, mut config: EmulatorConfig) -> EmulatorContext {
+pub fn run_emulator(rom_path: &str, config: EmulatorConfig) -> EmulatorContext {
     let rom_path_string = rom_path.to_string();
 
+    let rom_filename = Path::new(&rom_path)
+        .file_name()
+        .expect("filename must exist")
+        .to_str()
+        .expect("filename must be valid utf-8");
+
+    spawn_emulator(
+        move || Cartridge::new(&rom_path_string),
+        rom_filename.to_string(),
+        None,
+        config,
+    )
+}
+
+// Plays a 0-based track of a GBS file
+pub fn run_gbs(gbs_file: &GbsFile, track: u8, config: EmulatorConfig) -> EmulatorContext {
+    let cart = gbs_file.cartridge(track);
+
+    let rom_filename = Path::new(&gbs_file.path)
+        .file_name()
+        .expect("filename must exist")
+        .to_str()
+        .expect("filename must be valid utf-8");
+
+    spawn_emulator(
+        move || cart,
+        format!("{} #{}", rom_filename, track + 1),
+        Some(GbsTrack {
+            path: gbs_file.path.clone(),
+            track,
+            num_songs: gbs_file.header.num_songs,
+        }),
+        config,
+    )
+}
+
+fn spawn_emulator(
+    load_cartridge: impl FnOnce() -> Cartridge + Send + 'static,
+    rom_filename: String,
+    gbs: Option<GbsTrack>,
+    mut config: EmulatorConfig,
+) -> EmulatorContext {
     let (input_send, input_recv) = std::sync::mpsc::sync_channel::<InputEvent>(10);
+    let (command_send, command_recv) = std::sync::mpsc::sync_channel::<EmulatorCommand>(10);
 
     config.input_recv = Some(input_recv);
+    config.command_recv = Some(command_recv);
 
     let handle = std::thread::spawn(move || {
-        let cart = Cartridge::new(&rom_path_string);
+        let cart = load_cartridge();
         let mut gb = Gameboy::new(cart, Box::new(config));
         gb.boot();
         gb.run();
     });
 
-    let rom_filename = Path::new(&rom_path)
-        .file_name()
-        .expect("filename must exist")
-        .to_str()
-        .expect("filename must be valid utf-8");
+    EmulatorContext {
+        handle,
+        input_send,
+        command_send,
+        rom_filename,
+        gbs,
+        linked: None,
+    }
+}
+
+// Runs two Game Boys connected with a link cable in the same thread. The frames of the second
+// one are sent to the frontend through LinkedContext, its sound isn't played
+pub fn run_linked(rom_paths: [&str; 2], mut configs: [EmulatorConfig; 2]) -> EmulatorContext {
+    let rom_paths = rom_paths.map(|rom_path| rom_path.to_string());
+    let rom_filenames = rom_paths.clone().map(|rom_path| {
+        Path::new(&rom_path)
+            .file_name()
+            .expect("filename must exist")
+            .to_str()
+            .expect("filename must be valid utf-8")
+            .to_string()
+    });
+
+    let (input_send, input_recv) = std::sync::mpsc::sync_channel::<InputEvent>(10);
+    let (command_send, command_recv) = std::sync::mpsc::sync_channel::<EmulatorCommand>(10);
+    let (linked_input_send, linked_input_recv) = std::sync::mpsc::sync_channel::<InputEvent>(10);
+    let (linked_frame_send, linked_frame_recv) = std::sync::mpsc::sync_channel::<FrameBuffer>(1);
+
+    configs[0].input_recv = Some(input_recv);
+    configs[0].command_recv = Some(command_recv);
+    configs[1].input_recv = Some(linked_input_recv);
+    configs[1].sound_chan = None;
+
+    let frame_send = configs[0].frame_chan.take();
+
+    let handle = std::thread::spawn(move || {
+        let carts = rom_paths.map(|rom_path| Cartridge::new(&rom_path));
+        let mut linked = LinkedGameboys::new(carts, configs);
+        linked.boot();
+
+        while let Some([frame, linked_frame]) = linked.run_frame() {
+            // The frontend picks up the second frame right after the first one. Sending the
+            // first one fails once the frontend has dropped its receiver
+            let sent = frame_send
+                .as_ref()
+                .is_none_or(|frame_send| frame_send.send(*frame).is_ok())
+                && linked_frame_send.send(*linked_frame).is_ok();
+
+            if !sent {
+                linked.close();
+                break;
+            }
+        }
+    });
+
+    let [rom_filename, linked_rom_filename] = rom_filenames;
 
     EmulatorContext {
         handle,
         input_send,
-        rom_filename: rom_filename.to_string(),
+        command_send,
+        rom_filename,
+        gbs: None,
+        linked: Some(LinkedContext {
+            input_send: linked_input_send,
+            frame_recv: linked_frame_recv,
+            rom_filename: linked_rom_filename,
+        }),
     }
 }
 
@@ -204,6 +381,39 @@ fn scancode_to_gb_btn(scancode: Option<sdl2::keyboard::Scancode>) -> Option<GbBu
     }
 }
 
+fn scancode_to_ppu_debug_flag(
+    scancode: Option<sdl2::keyboard::Scancode>,
+    debug_flags: &mut ppu::ppu::PpuDebugFlags,
+) -> Option<&mut bool> {
+    match scancode {
+        Some(sdl2::keyboard::Scancode::F3) => Some(&mut debug_flags.hide_bg),
+        Some(sdl2::keyboard::Scancode::F4) => Some(&mut debug_flags.hide_window),
+        Some(sdl2::keyboard::Scancode::F5) => Some(&mut debug_flags.hide_sprites),
+        Some(sdl2::keyboard::Scancode::F6) => Some(&mut debug_flags.highlight_window),
+        Some(sdl2::keyboard::Scancode::F7) => Some(&mut debug_flags.highlight_sprites),
+        _ => None,
+    }
+}
+
+fn scancode_to_apu_channel(scancode: Option<sdl2::keyboard::Scancode>) -> Option<ApuChannel> {
+    match scancode {
+        Some(sdl2::keyboard::Scancode::Num1) => Some(ApuChannel::Pulse1),
+        Some(sdl2::keyboard::Scancode::Num2) => Some(ApuChannel::Pulse2),
+        Some(sdl2::keyboard::Scancode::Num3) => Some(ApuChannel::Wave),
+        Some(sdl2::keyboard::Scancode::Num4) => Some(ApuChannel::Noise),
+        _ => None,
+    }
+}
+
+// Previous and next track of a GBS file
+fn scancode_to_track_delta(scancode: Option<sdl2::keyboard::Scancode>) -> Option<i16> {
+    match scancode {
+        Some(sdl2::keyboard::Scancode::PageUp) => Some(-1),
+        Some(sdl2::keyboard::Scancode::PageDown) => Some(1),
+        _ => None,
+    }
+}
+
 fn controller_btn_to_gb_btn(btn: sdl2::controller::Button, _which: u32) -> Option<GbButton> {
     match btn {
         sdl2::controller::Button::DPadUp => Some(GbButton::GbButtonUp),
@@ -226,22 +436,8 @@ fn controller_axis_gb_btn(axis: sdl2::controller::Axis) -> Option<(GbButton, GbB
     }
 }
 
-fn rgb_from_gb_color(gb_color: u16) -> (u8, u8, u8) {
-    let red_intensity = gb_color & 0x1F;
-    let green_intensity = (gb_color >> 5) & 0x1F;
-    let blue_intensity = (gb_color >> 10) & 0x1F;
-
-    const INTENSITY: f64 = 8.22580;
-
-    (
-        (INTENSITY * f64::from(red_intensity as u8)) as u8,
-        (INTENSITY * f64::from(green_intensity as u8)) as u8,
-        (INTENSITY * f64::from(blue_intensity as u8)) as u8,
-    )
-}
-
 fn vsync_canvas(
-    rt: &FrameBuffer,
+    img: &RgbImage,
     texture: &mut sdl2::render::Texture,
     canvas: &mut sdl2::render::WindowCanvas,
     num_frames: &mut u64,
@@ -250,17 +446,9 @@ fn vsync_canvas(
 ) {
     texture
         .with_lock(None, |buffer, size| {
-            for x in 0..160 {
-                for y in 0..144 {
-                    let color = rt[y][x];
-                    let index = y * size + x * 3;
-
-                    let (r, g, b) = rgb_from_gb_color(color);
-
-                    buffer[index] = r;
-                    buffer[index + 1] = g;
-                    buffer[index + 2] = b;
-                }
+            let line_len = img.width * 3;
+            for (y, line) in img.pixels.chunks_exact(line_len).enumerate() {
+                buffer[y * size..y * size + line_len].copy_from_slice(line);
             }
         })
         .unwrap();
@@ -291,18 +479,55 @@ fn vsync_canvas(
     }
 }
 
-fn screenshot(rt: &FrameBuffer, rom_filename: &str) {
+fn update_viewer(
+    snapshot: &PpuSnapshot,
+    texture: &mut sdl2::render::Texture,
+    canvas: &mut sdl2::render::WindowCanvas,
+) {
+    let panels = [
+        (snapshot.render_tilemap(false), 0, 0),
+        (snapshot.render_tilemap(true), 256, 0),
+        (snapshot.render_tiles(), 512, 0),
+        (snapshot.render_palettes(), 512, 192),
+        (snapshot.render_sprites(), 0, 256),
+    ];
+
+    texture
+        .with_lock(None, |buffer, size| {
+            buffer.fill(0);
+
+            for (img, panel_x, panel_y) in panels.iter() {
+                for y in 0..img.height {
+                    for x in 0..img.width {
+                        let index = (panel_y + y) * size + (panel_x + x) * 3;
+
+                        let (r, g, b) = rgb_from_gb_color(img.get_pixel(x, y));
+
+                        buffer[index] = r;
+                        buffer[index + 1] = g;
+                        buffer[index + 2] = b;
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+    canvas.clear();
+    canvas.copy(texture, None, None).unwrap();
+    canvas.present();
+}
+
+fn screenshot(img: &RgbImage, rom_filename: &str) {
     if let Err(err) = std::fs::create_dir_all("screenshots") {
         eprintln!("Unable to create screenshot directory: {}", err);
         return;
     }
 
-    let mut bmp_img = bmp::Image::new(160, 144);
+    let mut bmp_img = bmp::Image::new(img.width as u32, img.height as u32);
 
-    for x in 0..160 {
-        for y in 0..144 {
-            let gb_color = rt[y][x];
-            let rgb_color = rgb_from_gb_color(gb_color);
+    for x in 0..img.width {
+        for y in 0..img.height {
+            let rgb_color = img.get_pixel(x, y);
             bmp_img.set_pixel(
                 x as u32,
                 y as u32,
@@ -323,6 +548,22 @@ fn screenshot(rt: &FrameBuffer, rom_filename: &str) {
     _ = bmp_img.save(format!("screenshots/{timestamp}-{rom_filename}.bmp"));
 }
 
+fn recording_path(rom_filename: &str, extension: &str) -> Option<std::path::PathBuf> {
+    if let Err(err) = std::fs::create_dir_all("recordings") {
+        eprintln!("Unable to create recordings directory: {}", err);
+        return None;
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("current time > UNIX_EPOCH")
+        .as_millis();
+
+    Some(std::path::PathBuf::from(format!(
+        "recordings/{timestamp}-{rom_filename}.{extension}"
+    )))
+}
+
 pub fn state_idle(event_pump: &mut sdl2::EventPump) -> Option<NextState> {
     for event in event_pump.poll_iter() {
         match event {
@@ -343,6 +584,8 @@ pub fn state_running(
     ctx: &Box<EmulatorContext>,
     canvas: &mut sdl2::render::WindowCanvas,
     frame_recv: &std::sync::mpsc::Receiver<FrameBuffer>,
+    sgb_frame_recv: &std::sync::mpsc::Receiver<SgbFrameBuffer>,
+    viewer_recv: &std::sync::mpsc::Receiver<Box<PpuSnapshot>>,
     event_pump: &mut sdl2::EventPump,
     sync_va: bool,
 ) -> Option<NextState> {
@@ -356,9 +599,55 @@ pub fn state_running(
         .create_texture_streaming(sdl2::pixels::PixelFormatEnum::RGB24, 160, 144)
         .unwrap();
 
+    // Presenting already waits for the display
+    let host_vsync =
+        canvas.info().flags & sdl2::sys::SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
+
+    let mut sgb_border = false;
+    let mut scale_filter = ScaleFilter::None;
+    let mut scaler = Scaler::new();
+
+    // Created on first use and hidden when closed, so its texture is only created once
+    let mut viewer: Option<sdl2::render::WindowCanvas> = None;
+    let viewer_texture_creator = std::cell::OnceCell::new();
+    let mut viewer_texture = None;
+    let mut viewer_open = false;
+    let mut ppu_debug_flags = ppu::ppu::PpuDebugFlags::default();
+    let mut recording = false;
+    let mut logging_vgm = false;
+    let mut channel_muted = [false; 4];
+    let mut channel_soloed = [false; 4];
+    let mut channel_capturing = [false; 4];
+
+    // Tab switches the input between linked Game Boys
+    let mut linked_input = false;
+
+    // Drop frames left over from a previously running game
+    while sgb_frame_recv.try_recv().is_ok() {}
+    while viewer_recv.try_recv().is_ok() {}
+
+    if ctx.linked.is_some() {
+        canvas
+            .set_logical_size(GB_SCREEN_WIDTH * 2, GB_SCREEN_HEIGHT)
+            .expect("canvast must set device independent resolution");
+        _ = canvas.window_mut().set_size(
+            GB_SCREEN_WIDTH * 2 * WINDOW_SIZE_MULT,
+            GB_SCREEN_HEIGHT * WINDOW_SIZE_MULT,
+        );
+    } else {
+        canvas
+            .set_logical_size(GB_SCREEN_HEIGHT, GB_SCREEN_WIDTH)
+            .expect("canvast must set device independent resolution");
+    }
+
     loop {
         let start_time = time::Instant::now();
 
+        let input_send = match &ctx.linked {
+            Some(linked) if linked_input => &linked.input_send,
+            _ => &ctx.input_send,
+        };
+
         for event in event_pump.poll_iter() {
             match event {
                 sdl2::event::Event::DropFile { filename, .. } => {
@@ -367,14 +656,73 @@ pub fn state_running(
                 sdl2::event::Event::Quit { .. } => {
                     return Some(NextState::Exit);
                 }
+                sdl2::event::Event::Window {
+                    window_id,
+                    win_event: sdl2::event::WindowEvent::Close,
+                    ..
+                } => {
+                    match &mut viewer {
+                        Some(viewer_canvas) if viewer_canvas.window().id() == window_id => {
+                            viewer_canvas.window_mut().hide();
+                            viewer_open = false;
+                            _ = ctx
+                                .command_send
+                                .send(EmulatorCommand::EnablePpuViewer(false));
+                        }
+                        // Closing the main window while the viewer is open doesn't send Quit
+                        _ => return Some(NextState::Exit),
+                    }
+                }
                 sdl2::event::Event::KeyDown {
-                    scancode, repeat, ..
+                    scancode,
+                    keymod,
+                    repeat,
+                    ..
                 } => {
                     if repeat {
                         continue;
                     }
-                    if let Some(gb_button) = scancode_to_gb_btn(scancode) {
-                        ctx.input_send
+                    let ctrl = keymod
+                        .intersects(sdl2::keyboard::Mod::LCTRLMOD | sdl2::keyboard::Mod::RCTRLMOD);
+                    let shift = keymod.intersects(
+                        sdl2::keyboard::Mod::LSHIFTMOD | sdl2::keyboard::Mod::RSHIFTMOD,
+                    );
+                    let alt = keymod
+                        .intersects(sdl2::keyboard::Mod::LALTMOD | sdl2::keyboard::Mod::RALTMOD);
+
+                    if let (true, Some(channel)) = (ctrl, scancode_to_apu_channel(scancode)) {
+                        // Ctrl+1..4 mutes, Ctrl+Shift+1..4 solos a sound channel
+                        let command = if shift {
+                            channel_soloed[channel as usize] = !channel_soloed[channel as usize];
+                            EmulatorCommand::SetChannelSoloed(
+                                channel,
+                                channel_soloed[channel as usize],
+                            )
+                        } else {
+                            channel_muted[channel as usize] = !channel_muted[channel as usize];
+                            EmulatorCommand::SetChannelMuted(
+                                channel,
+                                channel_muted[channel as usize],
+                            )
+                        };
+                        _ = ctx.command_send.send(command);
+                    } else if let (true, Some(channel)) = (alt, scancode_to_apu_channel(scancode)) {
+                        // Alt+1..4 captures a sound channel on its own to a WAV file
+                        let command = if channel_capturing[channel as usize] {
+                            Some(EmulatorCommand::StopChannelCapture(channel))
+                        } else {
+                            let name = format!("{}-{:?}", ctx.rom_filename, channel);
+                            recording_path(&name, "wav")
+                                .map(|path| EmulatorCommand::StartChannelCapture(channel, path))
+                        };
+
+                        if let Some(command) = command {
+                            channel_capturing[channel as usize] =
+                                !channel_capturing[channel as usize];
+                            _ = ctx.command_send.send(command);
+                        }
+                    } else if let Some(gb_button) = scancode_to_gb_btn(scancode) {
+                        input_send
                             .send(InputEvent {
                                 down: true,
                                 button: gb_button,
@@ -382,11 +730,82 @@ pub fn state_running(
                             .unwrap();
                     } else if scancode == Some(sdl2::keyboard::Scancode::F12) {
                         take_ss = true;
+                    } else if scancode == Some(sdl2::keyboard::Scancode::Tab)
+                        && ctx.linked.is_some()
+                    {
+                        linked_input = !linked_input;
+                    } else if let (Some(gbs), Some(delta)) =
+                        (&ctx.gbs, scancode_to_track_delta(scancode))
+                    {
+                        let track = (i16::from(gbs.track) + delta)
+                            .rem_euclid(i16::from(gbs.num_songs))
+                            as u8;
+                        return Some(NextState::LoadGbsTrack(gbs.path.clone(), track));
+                    } else if scancode == Some(sdl2::keyboard::Scancode::F10) && shift {
+                        let command = if logging_vgm {
+                            Some(EmulatorCommand::StopVgmLog)
+                        } else {
+                            recording_path(&ctx.rom_filename, "vgm")
+                                .map(EmulatorCommand::StartVgmLog)
+                        };
+
+                        if let Some(command) = command {
+                            logging_vgm = !logging_vgm;
+                            _ = ctx.command_send.send(command);
+                        }
+                    } else if scancode == Some(sdl2::keyboard::Scancode::F10) {
+                        let command = if recording {
+                            Some(EmulatorCommand::StopRecording)
+                        } else {
+                            recording_path(&ctx.rom_filename, "wav")
+                                .map(EmulatorCommand::StartRecording)
+                        };
+
+                        if let Some(command) = command {
+                            recording = !recording;
+                            _ = ctx.command_send.send(command);
+                        }
+                    } else if scancode == Some(sdl2::keyboard::Scancode::F9) {
+                        scale_filter = scale_filter.next();
+                    } else if scancode == Some(sdl2::keyboard::Scancode::F2) {
+                        viewer_open = !viewer_open;
+
+                        match &mut viewer {
+                            Some(viewer_canvas) if viewer_open => viewer_canvas.window_mut().show(),
+                            Some(viewer_canvas) => viewer_canvas.window_mut().hide(),
+                            None => {
+                                let viewer_canvas =
+                                    sdl2_create_viewer_window(canvas.window().subsystem());
+                                let texture_creator = viewer_texture_creator
+                                    .get_or_init(|| viewer_canvas.texture_creator());
+                                viewer_texture = Some(
+                                    texture_creator
+                                        .create_texture_streaming(
+                                            sdl2::pixels::PixelFormatEnum::RGB24,
+                                            VIEWER_WIDTH,
+                                            VIEWER_HEIGHT,
+                                        )
+                                        .unwrap(),
+                                );
+                                viewer = Some(viewer_canvas);
+                            }
+                        }
+
+                        _ = ctx
+                            .command_send
+                            .send(EmulatorCommand::EnablePpuViewer(viewer_open));
+                    } else if let Some(debug_flag) =
+                        scancode_to_ppu_debug_flag(scancode, &mut ppu_debug_flags)
+                    {
+                        *debug_flag = !*debug_flag;
+                        _ = ctx
+                            .command_send
+                            .send(EmulatorCommand::SetPpuDebugFlags(ppu_debug_flags));
                     }
                 }
                 sdl2::event::Event::KeyUp { scancode, .. } => {
                     if let Some(gb_button) = scancode_to_gb_btn(scancode) {
-                        ctx.input_send
+                        input_send
                             .send(InputEvent {
                                 down: false,
                                 button: gb_button,
@@ -396,7 +815,7 @@ pub fn state_running(
                 }
                 sdl2::event::Event::ControllerButtonDown { which, button, .. } => {
                     if let Some(gb_button) = controller_btn_to_gb_btn(button, which) {
-                        ctx.input_send
+                        input_send
                             .send(InputEvent {
                                 down: true,
                                 button: gb_button,
@@ -406,7 +825,7 @@ pub fn state_running(
                 }
                 sdl2::event::Event::ControllerButtonUp { which, button, .. } => {
                     if let Some(gb_button) = controller_btn_to_gb_btn(button, which) {
-                        ctx.input_send
+                        input_send
                             .send(InputEvent {
                                 down: false,
                                 button: gb_button,
@@ -418,13 +837,13 @@ pub fn state_running(
                     let dead_zone = 10_000;
 
                     if let Some((btn_neg, btn_pos)) = controller_axis_gb_btn(axis) {
-                        ctx.input_send
+                        input_send
                             .send(InputEvent {
                                 down: value < -dead_zone,
                                 button: btn_neg,
                             })
                             .unwrap();
-                        ctx.input_send
+                        input_send
                             .send(InputEvent {
                                 down: value > dead_zone,
                                 button: btn_pos,
@@ -438,8 +857,50 @@ pub fn state_running(
 
         match frame_recv.recv() {
             Ok(rt) => {
+                let img = if let Some(linked) = &ctx.linked {
+                    // Sent right after the frame of the first Game Boy
+                    let Ok(linked_rt) = linked.frame_recv.recv() else {
+                        panic!("frame channel should not get dropped");
+                    };
+
+                    let mut side_by_side =
+                        [[0; GB_SCREEN_WIDTH as usize * 2]; GB_SCREEN_HEIGHT as usize];
+                    for (line, (left, right)) in
+                        side_by_side.iter_mut().zip(rt.iter().zip(linked_rt.iter()))
+                    {
+                        let (line_left, line_right) = line.split_at_mut(left.len());
+                        line_left.copy_from_slice(left);
+                        line_right.copy_from_slice(right);
+                    }
+
+                    scaler.apply(scale_filter, &side_by_side)
+                } else if let Ok(sgb_rt) = sgb_frame_recv.try_recv() {
+                    // Super Game Boy frames are sent ahead of the Game Boy frame
+                    if !sgb_border {
+                        sgb_border = true;
+                        canvas
+                            .set_logical_size(SGB_SCREEN_WIDTH as u32, SGB_SCREEN_HEIGHT as u32)
+                            .expect("canvast must set device independent resolution");
+                    }
+
+                    scaler.apply(scale_filter, &sgb_rt)
+                } else {
+                    scaler.apply(scale_filter, &rt)
+                };
+
+                let query = texture.query();
+                if query.width as usize != img.width || query.height as usize != img.height {
+                    texture = texture_creator
+                        .create_texture_streaming(
+                            sdl2::pixels::PixelFormatEnum::RGB24,
+                            img.width as u32,
+                            img.height as u32,
+                        )
+                        .unwrap();
+                }
+
                 vsync_canvas(
-                    &rt,
+                    img,
                     &mut texture,
                     canvas,
                     &mut num_frames,
@@ -448,16 +909,24 @@ pub fn state_running(
                 );
                 if take_ss {
                     take_ss = false;
-                    screenshot(&rt, &ctx.rom_filename);
+                    screenshot(img, &ctx.rom_filename);
                 }
             }
             Err(_err) => panic!("frame channel should not get dropped"),
         }
 
+        if let (true, Some(viewer_canvas), Some(viewer_texture)) =
+            (viewer_open, &mut viewer, &mut viewer_texture)
+        {
+            if let Ok(snapshot) = viewer_recv.try_recv() {
+                update_viewer(&snapshot, viewer_texture, viewer_canvas);
+            }
+        }
+
         let elapsed = start_time.elapsed().as_micros().try_into().unwrap();
         let sleep_time = FRAME_TIME.saturating_sub(elapsed);
 
-        if sync_va && sleep_time > 0 {
+        if sync_va && !host_vsync && sleep_time > 0 {
             spin_sleep::sleep(time::Duration::from_micros(sleep_time));
         }
     }
@@ -466,8 +935,17 @@ pub fn state_running(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use apu::{
+        apu::{ApuChannel, APU_FREQ, APU_NUM_CHANNELS, APU_SAMPLES_PER_CHANNEL},
+        blip::AudioQuality,
+        highpass::HighPassFilter,
+        vgm_file::VgmWriter,
+    };
     use colored::Colorize;
+    use ppu::ppu::{FrameBlending, PPU};
     use rayon::prelude::*;
+    use serial::{link::TcpLink, peripheral::SerialPeripheral, SerialConnector};
+    use sgb::sgb::Sgb;
     use std::{
         collections::HashSet,
         fs,
@@ -490,8 +968,19 @@ mod tests {
 
     fn mts_runner(
         rom_path: &str,
-        _inputs: Option<Vec<GbButton>>,
+        inputs: Option<Vec<GbButton>>,
         comp_mode: Option<CompatibilityMode>,
+        model: Option<HardwareModel>,
+    ) -> Option<bool> {
+        mts_run(rom_path, inputs, comp_mode, model, None)
+    }
+
+    fn mts_run(
+        rom_path: &str,
+        inputs: Option<Vec<GbButton>>,
+        comp_mode: Option<CompatibilityMode>,
+        model: Option<HardwareModel>,
+        boot_rom_dir: Option<PathBuf>,
     ) -> Option<bool> {
         let (frame_send, frame_recv) = std::sync::mpsc::sync_channel::<ppu::ppu::FrameBuffer>(1);
         let (break_send, break_recv) = std::sync::mpsc::sync_channel::<(u16, u16, u16)>(1);
@@ -504,13 +993,48 @@ mod tests {
                 sync_video: false,
                 bp_chan: Some(break_send),
                 frame_chan: Some(frame_send),
+                sgb_frame_chan: None,
+                viewer_chan: None,
+                serial: SerialPeripheral::None,
                 sound_chan: None,
                 input_recv: None,
+                command_recv: None,
                 max_cycles: Some(T_CYCLES_PER_SECOND * 120),
                 comp_mode,
+                model,
+                boot_rom_dir,
+                dmg_palette: Some(DmgPalette::Classic),
+                frame_blending: FrameBlending::Off,
+                audio: AudioConfig::new(
+                    APU_FREQ,
+                    APU_SAMPLES_PER_CHANNEL,
+                    APU_NUM_CHANNELS,
+                    AudioQuality::Low,
+                    HighPassFilter::Accurate,
+                ),
             },
         );
 
+        // Buttons are pressed and released one after another until the emulator exits
+        if let Some(inputs) = inputs {
+            let input_send = emu_ctx.input_send.clone();
+
+            std::thread::spawn(move || {
+                for button in inputs.iter().cycle() {
+                    for down in [true, false] {
+                        let input = InputEvent {
+                            down,
+                            button: *button,
+                        };
+                        if input_send.send(input).is_err() {
+                            return;
+                        }
+                        std::thread::sleep(time::Duration::from_millis(20));
+                    }
+                }
+            });
+        }
+
         let test_passed = match break_recv.recv_timeout(time::Duration::from_secs(5)) {
             Ok(regs) => mts_passed(regs),
             Err(_) => false,
@@ -526,6 +1050,7 @@ mod tests {
         rom_path: &str,
         mut inputs: Option<Vec<GbButton>>,
         comp_mode: Option<CompatibilityMode>,
+        model: Option<HardwareModel>,
     ) -> Option<bool> {
         let root_path = PathBuf::from(rom_path.strip_prefix("tests/roms/").unwrap_or("unnamed"));
 
@@ -546,14 +1071,30 @@ mod tests {
             rom_path,
             EmulatorConfig {
                 comp_mode,
+                model,
+                boot_rom_dir: None,
+                // Note: snapshots are taken with the original DMG palette
+                dmg_palette: Some(DmgPalette::Classic),
+                frame_blending: FrameBlending::Off,
+                audio: AudioConfig::new(
+                    APU_FREQ,
+                    APU_SAMPLES_PER_CHANNEL,
+                    APU_NUM_CHANNELS,
+                    AudioQuality::Low,
+                    HighPassFilter::Accurate,
+                ),
                 enable_saving: false,
                 sync_audio: false,
                 // Note: sync video to guarantee receiving every frame for snapshot comparison
                 sync_video: true,
                 bp_chan: None,
                 frame_chan: Some(frame_send),
+                sgb_frame_chan: None,
+                viewer_chan: None,
+                serial: SerialPeripheral::None,
                 sound_chan: None,
                 input_recv: None,
+                command_recv: None,
                 max_cycles: Some(T_CYCLES_PER_SECOND * 120),
             },
         );
@@ -681,6 +1222,369 @@ mod tests {
         return Some(passed);
     }
 
+    // Mooneye style test ROM around code at 0x150, which jumps to TEST_PASS or TEST_FAIL
+    const TEST_PASS: u16 = 0x1000;
+    const TEST_FAIL: u16 = 0x1100;
+
+    fn build_test_rom(name: &str, cgb: bool, code: &[u8]) -> String {
+        let mut rom = vec![0x0; 0x8000];
+
+        // NOP; JP 0x150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x143] = if cgb { 0x80 } else { 0x00 };
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        });
+
+        // Fibonacci numbers in B, C, D, E, H, L for a pass, 0x42 for a failure. LD B, B; JR -2
+        let pass = [
+            0x06, 0x03, 0x0E, 0x05, 0x16, 0x08, 0x1E, 0x0D, 0x26, 0x15, 0x2E, 0x22, 0x40, 0x18,
+            0xFE,
+        ];
+        let fail = [0x06, 0x42, 0x48, 0x51, 0x5A, 0x63, 0x6C, 0x40, 0x18, 0xFE];
+        let pass_addr = usize::from(TEST_PASS);
+        let fail_addr = usize::from(TEST_FAIL);
+        rom[pass_addr..pass_addr + pass.len()].copy_from_slice(&pass);
+        rom[fail_addr..fail_addr + fail.len()].copy_from_slice(&fail);
+
+        let dir = PathBuf::from("target/test-roms");
+        fs::create_dir_all(&dir).expect("test ROM directory must be writable");
+
+        let path = dir.join(format!("{name}.gb"));
+        fs::write(&path, rom).expect("test ROM must be writable");
+        path.display().to_string()
+    }
+
+    // INC HL with HL in OAM during OAM scan corrupts OAM, the same outside of OAM doesn't
+    fn oam_bug_rom() -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        // Fills OAM with its offsets while the LCD is off, increments HL from the given address
+        // at the start of line 1 and counts the changed OAM bytes in B
+        let corrupt_oam = |addr: u16| {
+            let [addr_lo, addr_hi] = addr.to_le_bytes();
+
+            #[rustfmt::skip]
+            let code = [
+                0xF0, 0x44,             // LDH A, (LY)
+                0xFE, 0x90,             // CP 0x90
+                0x38, 0xFA,             // JR C, -6
+                0xAF,                   // XOR A
+                0xE0, 0x40,             // LDH (LCDC), A
+                0x21, 0x00, 0xFE,       // LD HL, 0xFE00
+                0x7D,                   // LD A, L
+                0x22,                   // LD (HL+), A
+                0x7D,                   // LD A, L
+                0xFE, 0xA0,             // CP 0xA0
+                0x20, 0xF9,             // JR NZ, -7
+                0x3E, 0x91,             // LD A, 0x91
+                0xE0, 0x40,             // LDH (LCDC), A
+                0x21, addr_lo, addr_hi, // LD HL, addr
+                0xF0, 0x44,             // LDH A, (LY)
+                0xFE, 0x01,             // CP 0x01
+                0x20, 0xFA,             // JR NZ, -6
+                0x23, 0x23, 0x23, 0x23, // INC HL
+                0x23, 0x23, 0x23, 0x23, // INC HL
+                0xF0, 0x44,             // LDH A, (LY)
+                0xFE, 0x90,             // CP 0x90
+                0x38, 0xFA,             // JR C, -6
+                0xAF,                   // XOR A
+                0xE0, 0x40,             // LDH (LCDC), A
+                0x06, 0x00,             // LD B, 0x00
+                0x21, 0x00, 0xFE,       // LD HL, 0xFE00
+                0x7D,                   // LD A, L
+                0xBE,                   // CP (HL)
+                0x28, 0x01,             // JR Z, +1
+                0x04,                   // INC B
+                0x2C,                   // INC L
+                0x7D,                   // LD A, L
+                0xFE, 0xA0,             // CP 0xA0
+                0x20, 0xF5,             // JR NZ, -11
+                0x3E, 0x91,             // LD A, 0x91
+                0xE0, 0x40,             // LDH (LCDC), A
+                0x78,                   // LD A, B
+                0xA7,                   // AND A
+            ];
+            code
+        };
+
+        let mut code = vec![0xF3]; // DI
+        code.extend_from_slice(&corrupt_oam(0xC000));
+        code.extend_from_slice(&[0xC2, fail_lo, fail_hi]); // JP NZ, TEST_FAIL
+        code.extend_from_slice(&corrupt_oam(0xFE00));
+        code.extend_from_slice(&[0xCA, fail_lo, fail_hi]); // JP Z, TEST_FAIL
+        code.extend_from_slice(&[0xC3, pass_lo, pass_hi]); // JP TEST_PASS
+
+        build_test_rom("oam_bug", false, &code)
+    }
+
+    // HALT with IME=0: the byte after it is read twice with an interrupt pending, once otherwise
+    fn halt_bug_rom() -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        #[rustfmt::skip]
+        let code = [
+            0xF3,                       // DI
+            0xAF,                       // XOR A
+            0xE0, 0x0F,                 // LDH (IF), A
+            0x3E, 0x01,                 // LD A, 0x01
+            0xE0, 0xFF,                 // LDH (IE), A
+            0xE0, 0x0F,                 // LDH (IF), A: VBlank pending
+            0x06, 0x00,                 // LD B, 0x00
+            0x76,                       // HALT
+            0x04,                       // INC B: executed twice
+            0x78,                       // LD A, B
+            0xFE, 0x02,                 // CP 0x02
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            0xAF,                       // XOR A
+            0xE0, 0x0F,                 // LDH (IF), A
+            0x3E, 0x04,                 // LD A, 0x04
+            0xE0, 0xFF,                 // LDH (IE), A
+            0x3E, 0xF0,                 // LD A, 0xF0
+            0xE0, 0x05,                 // LDH (TIMA), A
+            0x3E, 0x05,                 // LD A, 0x05
+            0xE0, 0x07,                 // LDH (TAC), A: timer wakes the CPU
+            0x06, 0x00,                 // LD B, 0x00
+            0x76,                       // HALT
+            0x04,                       // INC B: executed once
+            0x78,                       // LD A, B
+            0xFE, 0x01,                 // CP 0x01
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            0xC3, pass_lo, pass_hi,     // JP TEST_PASS
+        ];
+
+        build_test_rom("halt_bug", false, &code)
+    }
+
+    // STOP with KEY1 armed switches to double speed and back
+    fn speed_switch_rom() -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        #[rustfmt::skip]
+        let code = [
+            0xF3,                       // DI
+            0xAF,                       // XOR A
+            0xE0, 0xFF,                 // LDH (IE), A
+            0xE0, 0x0F,                 // LDH (IF), A
+            0x3E, 0x01,                 // LD A, 0x01
+            0xE0, 0x4D,                 // LDH (KEY1), A
+            0xF0, 0x4D,                 // LDH A, (KEY1)
+            0xE6, 0x81,                 // AND 0x81
+            0xFE, 0x01,                 // CP 0x01: armed
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            0x10, 0x00,                 // STOP
+            0xF0, 0x4D,                 // LDH A, (KEY1)
+            0xE6, 0x81,                 // AND 0x81
+            0xFE, 0x80,                 // CP 0x80: double speed
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            0x3E, 0x01,                 // LD A, 0x01
+            0xE0, 0x4D,                 // LDH (KEY1), A
+            0x10, 0x00,                 // STOP
+            0xF0, 0x4D,                 // LDH A, (KEY1)
+            0xE6, 0x81,                 // AND 0x81
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL: normal speed
+            0xC3, pass_lo, pass_hi,     // JP TEST_PASS
+        ];
+
+        build_test_rom("speed_switch", true, &code)
+    }
+
+    // Each IO register (FF00 + offset) must read the expected value right after boot
+    fn boot_hwio_rom(name: &str, cgb: bool, registers: &[(u8, u8)]) -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        let mut code = vec![];
+        for (offset, expected) in registers {
+            #[rustfmt::skip]
+            code.extend_from_slice(&[
+                0xF0, *offset,              // LDH A, (offset)
+                0xFE, *expected,            // CP expected
+                0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            ]);
+        }
+        code.extend_from_slice(&[0xC3, pass_lo, pass_hi]); // JP TEST_PASS
+
+        build_test_rom(name, cgb, &code)
+    }
+
+    // Leaves a marker in WRAM if the LCD was off, turns it on and unmaps itself, falling through to the cartridge at 0x100
+    fn write_boot_rom(path: &Path, size: usize, marker: u8) {
+        let mut boot_rom = vec![0x0; size];
+
+        #[rustfmt::skip]
+        let code = [
+            0x31, 0xFE, 0xFF,           // LD SP, 0xFFFE
+            0xF0, 0x40,                 // LDH A, (LCDC)
+            0xB7,                       // OR A
+            0x20, 0x05,                 // JR NZ, +5: the LCD starts off
+            0x3E, marker,               // LD A, marker
+            0xEA, 0x00, 0xC0,           // LD (0xC000), A
+            0x3E, 0x91,                 // LD A, 0x91
+            0xE0, 0x40,                 // LDH (LCDC), A
+        ];
+        boot_rom[..code.len()].copy_from_slice(&code);
+        // LD A, 0x01; LDH (BANK), A
+        boot_rom[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        fs::write(path, boot_rom).expect("boot ROM must be writable");
+    }
+
+    // Checks the marker of the boot ROM which ran and the state it handed over
+    fn boot_rom_cart(name: &str, marker: u8) -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        #[rustfmt::skip]
+        let code = [
+            0xFA, 0x00, 0xC0,           // LD A, (0xC000)
+            0xFE, marker,               // CP marker
+            0xC2, fail_lo, fail_hi,     // JP NZ, TEST_FAIL
+            0xFA, 0x00, 0x00,           // LD A, (0x0000)
+            0xFE, 0x31,                 // CP 0x31
+            0xCA, fail_lo, fail_hi,     // JP Z, TEST_FAIL: boot ROM still mapped
+            0xF0, 0x04,                 // LDH A, (DIV)
+            0xFE, 0x08,                 // CP 0x08
+            0xD2, fail_lo, fail_hi,     // JP NC, TEST_FAIL: DIV counts from power-on
+            0xC3, pass_lo, pass_hi,     // JP TEST_PASS
+        ];
+
+        build_test_rom(name, false, &code)
+    }
+
+    // STOP resets DIV, which doesn't tick until a pressed button wakes up the CPU
+    fn stop_rom() -> String {
+        let [fail_lo, fail_hi] = TEST_FAIL.to_le_bytes();
+        let [pass_lo, pass_hi] = TEST_PASS.to_le_bytes();
+
+        #[rustfmt::skip]
+        let code = [
+            0xF3,                       // DI
+            0xAF,                       // XOR A
+            0xE0, 0xFF,                 // LDH (IE), A
+            0xE0, 0x0F,                 // LDH (IF), A
+            0x3E, 0x10,                 // LD A, 0x10
+            0xE0, 0x00,                 // LDH (P1), A: select the buttons
+            0xF0, 0x04,                 // LDH A, (DIV)
+            0xFE, 0x20,                 // CP 0x20
+            0x38, 0xFA,                 // JR C, -6: DIV must be visibly reset
+            0xF0, 0x00,                 // LDH A, (P1)
+            0xE6, 0x0F,                 // AND 0x0F
+            0xFE, 0x0F,                 // CP 0x0F
+            0x20, 0xF8,                 // JR NZ, -8: wait until released
+            0x10, 0x00,                 // STOP
+            0xF0, 0x04,                 // LDH A, (DIV)
+            0xFE, 0x01,                 // CP 0x01
+            0xD2, fail_lo, fail_hi,     // JP NC, TEST_FAIL
+            0xF0, 0x00,                 // LDH A, (P1)
+            0xE6, 0x0F,                 // AND 0x0F
+            0xFE, 0x0F,                 // CP 0x0F
+            0xCA, fail_lo, fail_hi,     // JP Z, TEST_FAIL: woken up by the button
+            0xC3, pass_lo, pass_hi,     // JP TEST_PASS
+        ];
+
+        build_test_rom("stop", false, &code)
+    }
+
+    // Runs without a frontend, driven through the Gameboy API
+    fn headless_config() -> EmulatorConfig {
+        EmulatorConfig {
+            sound_chan: None,
+            frame_chan: None,
+            sgb_frame_chan: None,
+            bp_chan: None,
+            input_recv: None,
+            command_recv: None,
+            viewer_chan: None,
+            serial: SerialPeripheral::None,
+            enable_saving: false,
+            sync_audio: false,
+            sync_video: false,
+            max_cycles: None,
+            comp_mode: None,
+            model: None,
+            boot_rom_dir: None,
+            dmg_palette: None,
+            frame_blending: FrameBlending::Off,
+            audio: AudioConfig::new(
+                APU_FREQ,
+                APU_SAMPLES_PER_CHANNEL,
+                APU_NUM_CHANNELS,
+                AudioQuality::Low,
+                HighPassFilter::Accurate,
+            ),
+        }
+    }
+
+    // Plays a square wave on channel 1 forever
+    fn pulse_rom() -> String {
+        #[rustfmt::skip]
+        let code = [
+            0x3E, 0x80,                 // LD A, 0x80
+            0xE0, 0x26,                 // LDH (NR52), A
+            0xE0, 0x11,                 // LDH (NR11), A: 50% duty
+            0x3E, 0xF0,                 // LD A, 0xF0
+            0xE0, 0x12,                 // LDH (NR12), A: full volume
+            0xAF,                       // XOR A
+            0xE0, 0x13,                 // LDH (NR13), A
+            0x3E, 0x87,                 // LD A, 0x87
+            0xE0, 0x14,                 // LDH (NR14), A: trigger
+            0x18, 0xFE,                 // JR -2
+        ];
+
+        build_test_rom("pulse", false, &code)
+    }
+
+    // Two songs loaded at 0x400. INIT selects bank 2 and then bank 0, which must map bank 1,
+    // and plays a square wave on channel 1 when both markers are found
+    fn gbs_file() -> Vec<u8> {
+        let mut gbs = vec![0x0; 0x70];
+        gbs[0..4].copy_from_slice(b"GBS\x01");
+        gbs[0x04] = 2; // songs
+        gbs[0x05] = 1; // first song
+        gbs[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes()); // load
+        gbs[0x08..0x0A].copy_from_slice(&0x0400u16.to_le_bytes()); // INIT
+        gbs[0x0A..0x0C].copy_from_slice(&0x0480u16.to_le_bytes()); // PLAY
+        gbs[0x0C..0x0E].copy_from_slice(&0xFFFEu16.to_le_bytes()); // stack pointer
+        gbs[0x10..0x14].copy_from_slice(b"Test");
+
+        // Up to bank 2 at 0x8000-0xBFFF in the ROM
+        let mut data = vec![0x0; 0xC000 - 0x400];
+
+        #[rustfmt::skip]
+        let init = [
+            0x3E, 0x02,                 // LD A, 0x02
+            0xEA, 0x00, 0x20,           // LD (0x2000), A
+            0xFA, 0x00, 0x40,           // LD A, (0x4000)
+            0xFE, 0x22,                 // CP 0x22
+            0xC0,                       // RET NZ
+            0xAF,                       // XOR A
+            0xEA, 0x00, 0x20,           // LD (0x2000), A
+            0xFA, 0x00, 0x40,           // LD A, (0x4000)
+            0xFE, 0x11,                 // CP 0x11
+            0xC0,                       // RET NZ
+            0x3E, 0x80,                 // LD A, 0x80
+            0xE0, 0x26,                 // LDH (NR52), A
+            0xE0, 0x11,                 // LDH (NR11), A
+            0x3E, 0xF0,                 // LD A, 0xF0
+            0xE0, 0x12,                 // LDH (NR12), A
+            0x3E, 0x87,                 // LD A, 0x87
+            0xE0, 0x14,                 // LDH (NR14), A
+            0xC9,                       // RET
+        ];
+        data[..init.len()].copy_from_slice(&init);
+        data[0x80] = 0xC9; // PLAY: RET
+        data[0x4000 - 0x400] = 0x11;
+        data[0x8000 - 0x400] = 0x22;
+
+        gbs.extend_from_slice(&data);
+        gbs
+    }
+
     fn find_roms(rom_or_dir: &str) -> Vec<String> {
         let path = PathBuf::from(rom_or_dir);
 
@@ -716,15 +1620,434 @@ mod tests {
         return roms;
     }
 
+    // Sends a command through P14/P15, one 16 byte packet at a time
+    fn sgb_send(sgb: &mut Sgb, ppu: &mut PPU, command: &[u8]) {
+        for packet in command.chunks(16) {
+            // Reset pulse
+            sgb.write_p1(0x00, ppu);
+            sgb.write_p1(0x30, ppu);
+
+            for bit in 0..128 {
+                let byte = packet.get(bit / 8).copied().unwrap_or(0);
+                let line = if byte & (1 << (bit % 8)) != 0 {
+                    0x10
+                } else {
+                    0x20
+                };
+                sgb.write_p1(line, ppu);
+                sgb.write_p1(0x30, ppu);
+            }
+
+            // Stop bit
+            sgb.write_p1(0x20, ppu);
+            sgb.write_p1(0x30, ppu);
+        }
+    }
+
+    // Color 1 of the palette assigned to each 8x8 area on the row of the given scanline
+    fn sgb_row(ppu: &mut PPU, ly: usize) -> Vec<u16> {
+        let mut out = [0; 160];
+        let screen = ppu.get_sgb_screen().expect("SGB screen must exist");
+        screen.output_scanline(ly, &[(0, 1); 160], &mut out);
+        out.iter().step_by(8).copied().collect()
+    }
+
+    #[test]
+    fn sgb_commands() {
+        let ctx = std::rc::Rc::new(GbCtx {
+            cgb: false,
+            comp_mode: CompatibilityMode::ModeSgb,
+            model: HardwareModel::Sgb,
+            rom_path: String::new(),
+        });
+        let mut ppu = PPU::new(None, None, false, FrameBlending::Off, ctx);
+        let mut sgb = Sgb::new();
+
+        let color = |palette: u16| 0x10 * palette + 1;
+        let pal_command = |command: u8, first: u16, second: u16| {
+            let mut data = vec![command << 3 | 1];
+            for color in [0, color(first), 0, 0, color(second), 0, 0] {
+                data.extend_from_slice(&u16::to_le_bytes(color));
+            }
+            data
+        };
+
+        // PAL01, PAL23, then PAL12 replaces palette 1
+        sgb_send(&mut sgb, &mut ppu, &pal_command(0x00, 0, 9));
+        sgb_send(&mut sgb, &mut ppu, &pal_command(0x01, 2, 3));
+        sgb_send(&mut sgb, &mut ppu, &pal_command(0x03, 1, 2));
+        assert_eq!(sgb_row(&mut ppu, 0), vec![color(0); 20]);
+
+        // ATTR_BLK, inside and border of (1, 1)-(3, 3) set to palette 1
+        sgb_send(
+            &mut sgb,
+            &mut ppu,
+            &[0x04 << 3 | 1, 1, 0x1, 0x1, 1, 1, 3, 3],
+        );
+        for (ly, inside) in [(0, false), (8, true), (16, true), (24, true), (32, false)] {
+            let row = sgb_row(&mut ppu, ly);
+            for (x, color_1) in row.iter().enumerate() {
+                let palette = if inside && (1..=3).contains(&x) { 1 } else { 0 };
+                assert_eq!(*color_1, color(palette), "ATTR_BLK at {x}, {ly}");
+            }
+        }
+
+        // ATTR_LIN, palette 2 for the horizontal line 5
+        sgb_send(&mut sgb, &mut ppu, &[0x05 << 3 | 1, 1, 0x80 | 2 << 5 | 5]);
+        assert_eq!(sgb_row(&mut ppu, 40), vec![color(2); 20]);
+
+        // ATTR_DIV, palette 1 left of column 10, 2 on it and 3 right of it
+        sgb_send(
+            &mut sgb,
+            &mut ppu,
+            &[0x06 << 3 | 1, 3 | 1 << 2 | 2 << 4, 10],
+        );
+        let row = sgb_row(&mut ppu, 0);
+        assert_eq!(row[9], color(1));
+        assert_eq!(row[10], color(2));
+        assert_eq!(row[11], color(3));
+
+        // ATTR_CHR across two packets, palettes 0-3 repeating for three rows
+        let mut attr_chr = vec![0x07 << 3 | 2, 0, 0, 60, 0, 0];
+        attr_chr.extend_from_slice(&[0x1B; 15]);
+        sgb_send(&mut sgb, &mut ppu, &attr_chr);
+        for ly in [0, 8, 16] {
+            let row = sgb_row(&mut ppu, ly);
+            for (x, color_1) in row.iter().enumerate() {
+                assert_eq!(*color_1, color(x as u16 % 4), "ATTR_CHR at {x}, {ly}");
+            }
+        }
+        assert_eq!(sgb_row(&mut ppu, 24)[0], color(1));
+
+        // MASK_EN black, then cancel
+        sgb_send(&mut sgb, &mut ppu, &[0x17 << 3 | 1, 2]);
+        assert_eq!(sgb_row(&mut ppu, 0), vec![0; 20]);
+        sgb_send(&mut sgb, &mut ppu, &[0x17 << 3 | 1, 0]);
+        assert_eq!(sgb_row(&mut ppu, 0)[1], color(1));
+
+        // Without a border the Game Boy screen sits on the backdrop color
+        let screen = ppu.get_sgb_screen().unwrap();
+        let frame = screen.compose_frame(&[[0x1234; 160]; 144]);
+        assert_eq!(frame[0][0], 0);
+        assert_eq!(frame[40][48], 0x1234);
+        assert_eq!(frame[40 + 143][48 + 159], 0x1234);
+        assert_eq!(frame[40 + 144][48], 0);
+    }
+
+    #[test]
+    fn boot_rom() {
+        let dir = PathBuf::from("target/test-roms/boot");
+        fs::create_dir_all(&dir).expect("boot ROM directory must be writable");
+
+        let boot_roms = [(HardwareModel::Dmg, 0x42), (HardwareModel::Cgb, 0x43)];
+        for (model, marker) in boot_roms {
+            write_boot_rom(
+                &dir.join(model.boot_rom_file()),
+                model.boot_rom_size(),
+                marker,
+            );
+        }
+
+        for (model, marker) in boot_roms {
+            let rom = boot_rom_cart(&format!("boot_rom-{model:?}"), marker);
+            let passed = mts_run(&rom, None, None, Some(model), Some(dir.clone()));
+            assert_eq!(passed, Some(true), "{model:?} boot ROM");
+        }
+
+        // No mgb_boot.bin, the cartridge starts right away
+        let rom = boot_rom_cart("boot_rom-Mgb", 0x42);
+        let passed = mts_run(&rom, None, None, Some(HardwareModel::Mgb), Some(dir));
+        assert_eq!(passed, Some(false), "Mgb without a boot ROM");
+    }
+
+    #[test]
+    fn channel_capture() {
+        let mut gb = Gameboy::new(Cartridge::new(&pulse_rom()), Box::new(headless_config()));
+        gb.boot();
+
+        let path = PathBuf::from("target/test-roms/channel_capture.wav");
+        gb.start_channel_capture_file(ApuChannel::Pulse1, &path)
+            .expect("capture file must be writable");
+        gb.start_channel_capture(ApuChannel::Noise);
+
+        gb.run_cycles(T_CYCLES_PER_SECOND / 4);
+
+        let noise = gb.stop_channel_capture(ApuChannel::Noise).unwrap();
+        assert!(noise.len().abs_diff(APU_FREQ as usize) < 100);
+        assert!(noise.iter().all(|sample| *sample == 0));
+        // Written to the file, nothing is returned
+        assert!(gb
+            .stop_channel_capture(ApuChannel::Pulse1)
+            .unwrap()
+            .is_empty());
+
+        let wav = fs::read(&path).unwrap();
+        let channels = u16::from_le_bytes([wav[22], wav[23]]);
+        let data_size = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize;
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(channels, 1);
+        assert_eq!(data_size, wav.len() - 44);
+        assert!((data_size / 2).abs_diff(APU_FREQ as usize) < 100);
+
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        assert!(samples.iter().any(|sample| *sample > 8000));
+    }
+
+    #[test]
+    fn gbs_render() {
+        let gbs = GbsFile::parse("test.gbs", &gbs_file()).expect("GBS file must parse");
+        assert_eq!(gbs.header.num_songs, 2);
+        assert_eq!(gbs.header.load_addr, 0x400);
+        assert_eq!(gbs.header.title, "Test");
+        assert_eq!(gbs.default_track(), 0);
+
+        let cart = gbs.cartridge(1);
+        assert!(cart.gbs);
+        // MBC5+RAM, 64 KiB
+        assert_eq!(cart.header.cart_type, 0x1A);
+        assert_eq!(cart.data.len(), 0x10000);
+        // RST 0x08 jumps to load + 0x08, VBlank calls PLAY
+        assert_eq!(cart.data[0x08..0x0B], [0xC3, 0x08, 0x04]);
+        assert_eq!(cart.data[0x40..0x44], [0xCD, 0x80, 0x04, 0xD9]);
+        // The driver passes the track to INIT: LD A, 1; CALL INIT
+        let driver = &cart.data[0x150..0x200];
+        assert!(driver
+            .windows(5)
+            .any(|call| call == [0x3E, 0x01, 0xCD, 0x00, 0x04]));
+
+        let audio = AudioConfig::new(
+            APU_FREQ,
+            APU_SAMPLES_PER_CHANNEL,
+            APU_NUM_CHANNELS,
+            AudioQuality::Low,
+            HighPassFilter::Accurate,
+        );
+        assert!(gbs::gbs::render_to_wav(&gbs, 2, 1, Path::new("unused.wav"), audio).is_err());
+
+        let path = PathBuf::from("target/test-roms/gbs.wav");
+        gbs::gbs::render_to_wav(&gbs, 0, 1, &path, audio).expect("WAV must be rendered");
+
+        let wav = fs::read(&path).unwrap();
+        let data_size = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize;
+        let frames = data_size / (2 * usize::from(APU_NUM_CHANNELS));
+        assert_eq!(data_size, wav.len() - 44);
+        assert!(frames.abs_diff(APU_FREQ as usize) < APU_FREQ as usize / 50);
+
+        // Only played when bank 0 mapped bank 1
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        assert!(samples.iter().any(|sample| *sample > 4000));
+    }
+
+    #[test]
+    fn vgm_writer() {
+        let path = PathBuf::from("target/test-roms/vgm_writer.vgm");
+        fs::create_dir_all("target/test-roms").unwrap();
+
+        let mut vgm = VgmWriter::create(&path).expect("VGM file must be writable");
+        vgm.write_register(0xFF26, 0x80).unwrap();
+        // 10 samples at 44.1 kHz
+        vgm.clock(1000);
+        vgm.write_register(0xFF12, 0xF0).unwrap();
+        // One second in total
+        vgm.clock(T_CYCLES_PER_SECOND - 1000);
+        vgm.write_register(0xFF14, 0x87).unwrap();
+        // One NTSC frame, 735 samples
+        vgm.clock(69906);
+        vgm.finish().unwrap();
+
+        let vgm = fs::read(&path).unwrap();
+        let dword = |offset: usize| {
+            u32::from_le_bytes([
+                vgm[offset],
+                vgm[offset + 1],
+                vgm[offset + 2],
+                vgm[offset + 3],
+            ])
+        };
+
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(dword(0x04) as usize, vgm.len() - 4);
+        assert_eq!(dword(0x08), 0x161);
+        assert_eq!(dword(0x18), 44100 + 735);
+        assert_eq!(dword(0x34), 0x100 - 0x34);
+        assert_eq!(dword(0x80), 4_194_304);
+
+        #[rustfmt::skip]
+        let commands = [
+            0xB3, 0x16, 0x80,           // NR52
+            0x79,                       // Wait 10 samples
+            0xB3, 0x02, 0xF0,           // NR12
+            0x61, 0x3A, 0xAC,           // Wait 44090 samples
+            0xB3, 0x04, 0x87,           // NR14
+            0x62,                       // Wait 735 samples
+            0x66,                       // End
+        ];
+        assert_eq!(vgm[0x100..], commands);
+    }
+
+    // Exchanges SB over the link cable, with the internal clock after a delay so the other side
+    // already waits. Ends with the received byte in B and DIV at the end of the transfer in C
+    fn link_rom(name: &str, sb: u8, internal_clock: bool) -> String {
+        #[rustfmt::skip]
+        let mut code = vec![
+            0x3E, sb,   // LD A, sb
+            0xE0, 0x01, // LDH (SB), A
+        ];
+        if internal_clock {
+            #[rustfmt::skip]
+            code.extend_from_slice(&[
+                0x06, 0x00, // LD B, 0
+                0x05,       // DEC B
+                0x20, 0xFD, // JR NZ, -3
+            ]);
+        }
+        #[rustfmt::skip]
+        code.extend_from_slice(&[
+            0x3E, 0x80 | internal_clock as u8, // LD A, sc
+            0xE0, 0x02,                        // LDH (SC), A
+            0xF0, 0x02,                        // LDH A, (SC)
+            0xCB, 0x7F,                        // BIT 7, A
+            0x20, 0xFA,                        // JR NZ, -6
+            0xF0, 0x04,                        // LDH A, (DIV)
+            0x4F,                              // LD C, A
+            0xF0, 0x01,                        // LDH A, (SB)
+            0x47,                              // LD B, A
+            0x18, 0xFE,                        // JR -2
+        ]);
+
+        build_test_rom(name, false, &code)
+    }
+
+    // Serial on the internal clock, returns the received byte and the M-cycles it took
+    fn link_send(link: &mut dyn SerialConnector, byte: u8, double_speed: bool) -> (u8, u32) {
+        link.send_byte(byte);
+
+        let mut reply = None;
+        for cycles in 0..100_000 {
+            link.clock(double_speed);
+            reply = reply.or_else(|| link.take());
+            if reply.is_some() && link.finish_transfer() {
+                return (reply.unwrap(), cycles);
+            }
+        }
+        panic!("transfer of {byte:#04X} must finish");
+    }
+
+    // Serial on the external clock
+    fn link_receive(link: &mut dyn SerialConnector, sb: u8) -> u8 {
+        for _ in 0..1_000_000 {
+            link.clock(false);
+            if let Some(byte) = link.recv_byte(sb) {
+                return byte;
+            }
+        }
+        panic!("{sb:#04X} must be received");
+    }
+
+    #[test]
+    fn linked_gameboys() {
+        let carts = [
+            Cartridge::new(&link_rom("link_internal", 0x12, true)),
+            Cartridge::new(&link_rom("link_external", 0x34, false)),
+        ];
+        let mut linked = LinkedGameboys::new(carts, [headless_config(), headless_config()]);
+        linked.boot();
+
+        for _ in 0..10 {
+            assert!(linked.run_frame().is_some());
+        }
+
+        let results = [0, 1].map(|i| {
+            let cpu = linked.get_gameboy_mut(i).get_cpu();
+            (cpu.b().get(), cpu.c().get())
+        });
+        linked.close();
+
+        let [(internal_sb, internal_div), (external_sb, external_div)] = results;
+        assert_eq!(internal_sb, 0x34);
+        assert_eq!(external_sb, 0x12);
+        // Both finish with the 8th bit, give or take the 128 M-cycles of a slice
+        let div_delta = external_div.wrapping_sub(internal_div) as i8;
+        assert!(
+            div_delta.abs() <= 2,
+            "DIV {internal_div:#04X} vs {external_div:#04X}"
+        );
+    }
+
+    #[test]
+    fn tcp_link() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let host = std::thread::spawn(move || {
+            let mut link = TcpLink::accept(listener).expect("joiner must connect");
+            let received = [0x55, 0x66, 0x77].map(|sb| link_receive(&mut link, sb));
+            (received, link.is_connected())
+        });
+
+        let mut link = TcpLink::join(address).expect("host must accept");
+        assert_eq!(link_send(&mut link, 0x12, false).0, 0x55);
+
+        // Same time in double speed, twice the M-cycles
+        let (reply, normal) = link_send(&mut link, 0x34, false);
+        assert_eq!(reply, 0x66);
+        let (reply, double) = link_send(&mut link.share(), 0x56, true);
+        assert_eq!(reply, 0x77);
+        assert!(double > normal * 3 / 2, "{double} vs {normal} M-cycles");
+
+        let (received, connected) = host.join().unwrap();
+        assert_eq!(received, [0x12, 0x34, 0x56]);
+        assert!(connected);
+    }
+
+    #[test]
+    fn tcp_link_reconnect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let host = std::thread::spawn(move || {
+            let mut link = TcpLink::accept(listener).expect("joiner must connect");
+            [0x55, 0x66].map(|sb| link_receive(&mut link, sb))
+        });
+
+        let mut link = TcpLink::join(address).expect("host must accept");
+        assert_eq!(link_send(&mut link, 0x12, false).0, 0x55);
+        drop(link);
+
+        // The host notices the lost connection and accepts the next one
+        let mut link = TcpLink::join(address).expect("host must accept again");
+        assert_eq!(link_send(&mut link, 0x34, false).0, 0x66);
+        assert_eq!(host.join().unwrap(), [0x12, 0x34]);
+    }
+
     #[test]
     fn all() {
-        type RunnerFn =
-            fn(path: &str, Option<Vec<GbButton>>, Option<CompatibilityMode>) -> Option<bool>;
+        type RunnerFn = fn(
+            path: &str,
+            Option<Vec<GbButton>>,
+            Option<CompatibilityMode>,
+            Option<HardwareModel>,
+        ) -> Option<bool>;
         type RunnerWithArgs = (
             RunnerFn,
             String,
             Option<Vec<GbButton>>,
             Option<CompatibilityMode>,
+            Option<HardwareModel>,
+        );
+        // ROM or directory of ROMs
+        type TestEntry<'a> = (
+            RunnerFn,
+            &'a str,
+            Option<Vec<GbButton>>,
+            Option<CompatibilityMode>,
+            Option<HardwareModel>,
         );
 
         fn submenu(item_index: usize) -> Option<Vec<GbButton>> {
@@ -736,50 +2059,84 @@ mod tests {
             Some(input_vec)
         }
 
+        let oam_bug_rom = oam_bug_rom();
+        let halt_bug_rom = halt_bug_rom();
+        let speed_switch_rom = speed_switch_rom();
+        let stop_rom = stop_rom();
+        // LY, STAT, NR52 and DMA, https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+        let boot_hwio_dmg0_rom = boot_hwio_rom(
+            "boot_hwio-dmg0",
+            false,
+            &[(0x44, 0x91), (0x41, 0x81), (0x26, 0xF1)],
+        );
+        let boot_hwio_sgb_rom =
+            boot_hwio_rom("boot_hwio-sgb", false, &[(0x26, 0xF0), (0x46, 0xFF)]);
+        let boot_hwio_cgb_rom = boot_hwio_rom("boot_hwio-cgb", true, &[(0x26, 0xF1), (0x46, 0x00)]);
+
         #[rustfmt::skip]
-        let test_roms: Vec<(RunnerFn, &str, Option<Vec<GbButton>>, Option<CompatibilityMode>)>  = vec!(
-            (snapshot_runner,   "tests/roms/blargg/cpu_instrs/cpu_instrs.gb",       None,           None),
-            (snapshot_runner,   "tests/roms/blargg/cpu_instrs/",                    None,           Some(CompatibilityMode::ModeCgbDmg)),
-            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.0.gb",                submenu(0),     None),
-            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.1.gb",                submenu(1),     None),
-            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.2.gb",                submenu(2),     None),
-            (snapshot_runner,   "tests/roms/blargg/instr_timing/",                  None,           None),
-            (snapshot_runner,   "tests/roms/blargg/dmg_sound/",                     None,           None),
-            (snapshot_runner,   "tests/roms/blargg/cgb_sound/",                     None,           None),
-            (snapshot_runner,   "tests/roms/blargg/mem_timing/",                    None,           None),
-            (snapshot_runner,   "tests/roms/blargg/mem_timing-2/",                  None,           None),
-            (snapshot_runner,   "tests/roms/blargg/interrupt_time/",                None,           None),
-            (snapshot_runner,   "tests/roms/magen/",                                None,           None),
-            (snapshot_runner,   "tests/roms/mts/manual-only/sprite_priority.gb",    None,           None),
-            (snapshot_runner,   "tests/roms/acid/",                                 None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/boot_regs-dmgABC.gb",    None,           Some(CompatibilityMode::ModeDmg)),
-            (mts_runner,        "tests/roms/mts/acceptance/boot_hwio-dmgABCmgb.gb", None,           Some(CompatibilityMode::ModeDmg)),
-            (mts_runner,        "tests/roms/mts/acceptance/bits/unused_hwio-GS.gb", None,           Some(CompatibilityMode::ModeDmg)),
-            (mts_runner,        "tests/roms/mts/acceptance/bits/",                  None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/instr/",                 None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/interrupts/",            None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/oam_dma/",               None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/ppu/",                   None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/timer/",                 None,           None),
-            (mts_runner,        "tests/roms/mts/acceptance/",                       None,           None),
-            (mts_runner,        "tests/roms/mts/emulator-only/mbc1/",               None,           None),
-            (mts_runner,        "tests/roms/mts/emulator-only/mbc2/",               None,           None),
-            (mts_runner,        "tests/roms/mts/emulator-only/mbc5/",               None,           None),
-            (mts_runner,        "tests/roms/mts/misc/bits/",                        None,           None),
-            (mts_runner,        "tests/roms/mts/misc/ppu/",                         None,           None),
-            (mts_runner,        "tests/roms/mts/misc/boot_hwio-C.gb",               None,           None),
-            (mts_runner,        "tests/roms/mts/misc/boot_regs-cgb.gb",             None,           None),
-
-            // (mts_runner, "tests/roms/mts/acceptance/serial/", None),
+        let test_roms: Vec<TestEntry>  = vec!(
+            (snapshot_runner,   "tests/roms/blargg/cpu_instrs/cpu_instrs.gb",       None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/cpu_instrs/",                    None,           Some(CompatibilityMode::ModeCgbDmg),      None),
+            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.0.gb",                submenu(0),     None,                                     None),
+            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.1.gb",                submenu(1),     None,                                     None),
+            (snapshot_runner,   "tests/roms/rtc3test/rtc3test.2.gb",                submenu(2),     None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/instr_timing/",                  None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/dmg_sound/",                     None,           Some(CompatibilityMode::ModeDmg),         None),
+            (snapshot_runner,   "tests/roms/blargg/cgb_sound/",                     None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/mem_timing/",                    None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/mem_timing-2/",                  None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/blargg/interrupt_time/",                None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/magen/",                                None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/mts/manual-only/sprite_priority.gb",    None,           None,                                     None),
+            (snapshot_runner,   "tests/roms/acid/",                                 None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/boot_regs-dmgABC.gb",    None,           None,                                     Some(HardwareModel::Dmg)),
+            (mts_runner,        "tests/roms/mts/acceptance/boot_hwio-dmgABCmgb.gb", None,           None,                                     Some(HardwareModel::Dmg)),
+            (mts_runner,        "tests/roms/mts/acceptance/boot_hwio-dmgABCmgb.gb", None,           None,                                     Some(HardwareModel::Mgb)),
+            (mts_runner,        "tests/roms/mts/acceptance/boot_div-dmgABCmgb.gb",  None,           None,                                     Some(HardwareModel::Dmg)),
+            (mts_runner,        "tests/roms/mts/acceptance/boot_div-dmgABCmgb.gb",  None,           None,                                     Some(HardwareModel::Mgb)),
+            (mts_runner,        &boot_hwio_dmg0_rom,                                None,           None,                                     Some(HardwareModel::Dmg0)),
+            (mts_runner,        &boot_hwio_sgb_rom,                                 None,           None,                                     Some(HardwareModel::Sgb)),
+            (mts_runner,        &boot_hwio_sgb_rom,                                 None,           None,                                     Some(HardwareModel::Sgb2)),
+            (mts_runner,        &boot_hwio_cgb_rom,                                 None,           None,                                     Some(HardwareModel::Cgb)),
+            (mts_runner,        &boot_hwio_cgb_rom,                                 None,           None,                                     Some(HardwareModel::Agb)),
+            (mts_runner,        "tests/roms/mts/acceptance/bits/unused_hwio-GS.gb", None,           Some(CompatibilityMode::ModeDmg),         None),
+            (mts_runner,        "tests/roms/mts/acceptance/bits/",                  None,           None,                                     None),
+            (mts_runner,        &oam_bug_rom,                                       None,           Some(CompatibilityMode::ModeDmg),         None),
+            (mts_runner,        &halt_bug_rom,                                      None,           None,                                     None),
+            (mts_runner,        &speed_switch_rom,                                  None,           None,                                     None),
+            (mts_runner,        &stop_rom,                                          Some(vec![GbButton::GbButtonA]), None,                    None),
+            (mts_runner,        "tests/roms/mts/acceptance/instr/",                 None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/interrupts/",            None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/oam_dma/",               None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/ppu/",                   None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/timer/",                 None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/acceptance/",                       None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/emulator-only/mbc1/",               None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/emulator-only/mbc2/",               None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/emulator-only/mbc5/",               None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/misc/bits/",                        None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/misc/ppu/",                         None,           None,                                     None),
+            (mts_runner,        "tests/roms/mts/misc/boot_hwio-C.gb",               None,           None,                                     Some(HardwareModel::Cgb)),
+            (mts_runner,        "tests/roms/mts/misc/boot_hwio-C.gb",               None,           None,                                     Some(HardwareModel::CgbE)),
+            (mts_runner,        "tests/roms/mts/misc/boot_regs-cgb.gb",             None,           None,                                     Some(HardwareModel::Cgb)),
+            (mts_runner,        "tests/roms/mts/misc/boot_regs-cgb.gb",             None,           None,                                     Some(HardwareModel::CgbE)),
+
+            (mts_runner,        "tests/roms/mts/acceptance/serial/",                None,           Some(CompatibilityMode::ModeDmg),         None),
         );
 
         let mut rom_files: Vec<RunnerWithArgs> = test_roms
             .iter()
-            .flat_map(|(runner, rom_or_dir, inputs, comp_mode)| {
+            .flat_map(|(runner, rom_or_dir, inputs, comp_mode, model)| {
                 let roms_with_runners = find_roms(rom_or_dir)
                     .iter()
                     .map(|path: &String| {
-                        (runner.clone(), path.to_string(), inputs.clone(), *comp_mode)
+                        (
+                            *runner,
+                            path.to_string(),
+                            inputs.clone(),
+                            *comp_mode,
+                            *model,
+                        )
                     })
                     .collect::<Vec<RunnerWithArgs>>();
 
@@ -787,24 +2144,34 @@ mod tests {
             })
             .collect::<Vec<RunnerWithArgs>>();
 
-        // Remove duplicates
+        // Remove duplicates, ROMs listed with a model run once per model
         {
             let mut roms_set = HashSet::new();
-            rom_files.retain(|(_, path, _, _)| {
-                if roms_set.contains(path) {
+            let mut model_roms_set = HashSet::new();
+            rom_files.retain(|(_, path, _, _, model)| {
+                let model_rom = format!("{path} {model:?}");
+                if roms_set.contains(path)
+                    && (model.is_none() || model_roms_set.contains(&model_rom))
+                {
                     return false;
                 }
                 roms_set.insert(path.clone());
+                model_roms_set.insert(model_rom);
                 return true;
             });
         }
 
         let results = rom_files
             .par_iter()
-            .map(|(runner, rom_path, inputs, comp_mode)| {
-                (rom_path, runner(rom_path, inputs.clone(), *comp_mode))
+            .map(|(runner, rom_path, inputs, comp_mode, model)| {
+                let name = match model {
+                    Some(model) => format!("{rom_path} ({model:?})"),
+                    None => rom_path.to_string(),
+                };
+
+                (name, runner(rom_path, inputs.clone(), *comp_mode, *model))
             })
-            .collect::<Vec<(&String, Option<bool>)>>();
+            .collect::<Vec<(String, Option<bool>)>>();
 
         results.iter().for_each(|(path, pass_opt)| {
             if let Some(is_passing) = pass_opt {
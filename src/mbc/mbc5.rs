@@ -13,6 +13,9 @@ pub struct MBC5 {
     num_rom_banks: usize,
     num_ram_banks: usize,
 
+    // GBS rips are written for MBC1, which maps bank 1 when bank 0 is selected
+    bank_0_as_1: bool,
+
     save_path: Option<String>,
 }
 
@@ -26,9 +29,17 @@ impl MBC5 {
             num_rom_banks: 1,
             num_ram_banks: 1,
             ram_enabled: false,
+            bank_0_as_1: false,
             save_path: None,
         }
     }
+
+    fn select_rom_bank(&mut self, bank: usize) {
+        self.rom_bank = match bank {
+            0 if self.bank_0_as_1 => 1,
+            bank => bank % self.num_rom_banks,
+        };
+    }
 }
 
 impl mbc::MBC for MBC5 {
@@ -44,6 +55,7 @@ impl mbc::MBC for MBC5 {
 
         self.num_ram_banks = ram_banks.num_banks;
         self.ram = vec![0; ram_banks.size_bytes];
+        self.bank_0_as_1 = cartridge.gbs;
 
         match hdr.cart_type {
             0x1B | 0x1E => {
@@ -85,11 +97,10 @@ impl mbc::MBC for MBC5 {
                 self.ram_enabled = data & 0xF == 0xA;
             }
             0x2000..=0x2FFF => {
-                self.rom_bank = ((self.rom_bank & 0x100) | usize::from(data)) % self.num_rom_banks;
+                self.select_rom_bank((self.rom_bank & 0x100) | usize::from(data));
             }
             0x3000..=0x3FFF => {
-                self.rom_bank =
-                    ((self.rom_bank & 0xFF) | (usize::from(data & 1) << 8)) % self.num_rom_banks;
+                self.select_rom_bank((self.rom_bank & 0xFF) | (usize::from(data & 1) << 8));
             }
             0x4000..=0x5FFF => {
                 self.ram_bank = (usize::from(data) & 0xF) % self.num_ram_banks;
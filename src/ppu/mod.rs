@@ -1 +1,3 @@
+pub mod palettes;
 pub mod ppu;
+pub mod viewer;
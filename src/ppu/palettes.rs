@@ -0,0 +1,223 @@
+use crate::{cartridge::cartridge::CartridgeHeader, CompatibilityMode, DmgPalette};
+
+// Colorization of DMG games when running on the CGB. The CGB boot ROM checks if the game
+// was licensed by Nintendo and picks a set of palettes based on a checksum of the title.
+// Titles with colliding checksums are told apart by the 4th letter of the title.
+// The player can override the selection with a button combination during the boot logo.
+// https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes
+
+pub struct CompatPalette {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+const fn rgb555(rgb: u32) -> u16 {
+    let r = ((rgb >> 16) & 0xFF) >> 3;
+    let g = ((rgb >> 8) & 0xFF) >> 3;
+    let b = (rgb & 0xFF) >> 3;
+    (r | (g << 5) | (b << 10)) as u16
+}
+
+const fn palette(colors: [u32; 4]) -> [u16; 4] {
+    [
+        rgb555(colors[0]),
+        rgb555(colors[1]),
+        rgb555(colors[2]),
+        rgb555(colors[3]),
+    ]
+}
+
+#[rustfmt::skip]
+const RAW_PALETTES: [[u16; 4]; 24] = [
+    /* 0x00 */ palette([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]),
+    /* 0x01 */ palette([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+    /* 0x02 */ palette([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000]),
+    /* 0x03 */ palette([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]),
+    /* 0x04 */ palette([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000]),
+    /* 0x05 */ palette([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF]),
+    /* 0x06 */ palette([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]),
+    /* 0x07 */ palette([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000]),
+    /* 0x08 */ palette([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]),
+    /* 0x09 */ palette([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000]),
+    /* 0x0A */ palette([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]),
+    /* 0x0B */ palette([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]),
+    /* 0x0C */ palette([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000]),
+    /* 0x0D */ palette([0xFFFFFF, 0xADAD84, 0x42737B, 0x000000]),
+    /* 0x0E */ palette([0xFFFFFF, 0xFF7300, 0x944200, 0x000000]),
+    /* 0x0F */ palette([0xFFFFFF, 0x5ABDFF, 0xFF0000, 0x0000FF]),
+    /* 0x10 */ palette([0xFFFFFF, 0x00FF00, 0x318400, 0x004A00]),
+    /* 0x11 */ palette([0xFFFF9C, 0x94B5FF, 0x639473, 0x003A3A]),
+    /* 0x12 */ palette([0xFFC542, 0xFFD600, 0x943A00, 0x4A0000]),
+    /* 0x13 */ palette([0xFFFFFF, 0xFFFF7B, 0x0084FF, 0xFF0000]),
+    /* 0x14 */ palette([0xFFFFCE, 0x63EFEF, 0x9C8431, 0x5A5A5A]),
+    /* 0x15 */ palette([0xFFFFFF, 0xFF9C00, 0xFF0000, 0x000000]),
+    /* 0x16 */ palette([0xFFFFFF, 0xFFCE00, 0x9C6300, 0x000000]),
+    /* 0x17 */ palette([0xA59CFF, 0xFFFF00, 0x006300, 0x000000]),
+];
+
+// Palette combinations as indices to RAW_PALETTES (obj0, obj1, bg)
+#[rustfmt::skip]
+const PALETTE_COMBINATIONS: [(u8, u8, u8); 51] = [
+    /* 0x00 */ (0x01, 0x01, 0x00), // Right + A (default)
+    /* 0x01 */ (0x02, 0x02, 0x02), // Right
+    /* 0x02 */ (0x01, 0x0A, 0x13),
+    /* 0x03 */ (0x03, 0x03, 0x03), // Down + A
+    /* 0x04 */ (0x01, 0x01, 0x16),
+    /* 0x05 */ (0x04, 0x04, 0x04), // Up
+    /* 0x06 */ (0x05, 0x05, 0x05), // Right + B
+    /* 0x07 */ (0x06, 0x06, 0x06), // Left + B
+    /* 0x08 */ (0x07, 0x07, 0x07), // Down
+    /* 0x09 */ (0x01, 0x0B, 0x14),
+    /* 0x0A */ (0x0B, 0x01, 0x15),
+    /* 0x0B */ (0x01, 0x0B, 0x0A),
+    /* 0x0C */ (0x01, 0x0A, 0x0B),
+    /* 0x0D */ (0x01, 0x0B, 0x01),
+    /* 0x0E */ (0x01, 0x0A, 0x0B),
+    /* 0x0F */ (0x01, 0x0A, 0x12),
+    /* 0x10 */ (0x0B, 0x0A, 0x04),
+    /* 0x11 */ (0x01, 0x0A, 0x07),
+    /* 0x12 */ (0x01, 0x0B, 0x13),
+    /* 0x13 */ (0x0A, 0x01, 0x0B),
+    /* 0x14 */ (0x0E, 0x0E, 0x04),
+    /* 0x15 */ (0x01, 0x0A, 0x00),
+    /* 0x16 */ (0x0E, 0x0F, 0x0D), // Super Mario Land
+    /* 0x17 */ (0x01, 0x0B, 0x16),
+    /* 0x18 */ (0x0B, 0x0A, 0x11),
+    /* 0x19 */ (0x0A, 0x01, 0x02),
+    /* 0x1A */ (0x01, 0x01, 0x12),
+    /* 0x1B */ (0x01, 0x0A, 0x14),
+    /* 0x1C */ (0x08, 0x08, 0x08), // Up + B
+    /* 0x1D */ (0x01, 0x0A, 0x17),
+    /* 0x1E */ (0x0B, 0x01, 0x07),
+    /* 0x1F */ (0x0A, 0x0A, 0x15),
+    /* 0x20 */ (0x01, 0x0B, 0x0C),
+    /* 0x21 */ (0x0E, 0x0A, 0x16),
+    /* 0x22 */ (0x0B, 0x0B, 0x02),
+    /* 0x23 */ (0x01, 0x0A, 0x03),
+    /* 0x24 */ (0x01, 0x01, 0x11),
+    /* 0x25 */ (0x0E, 0x0A, 0x0D),
+    /* 0x26 */ (0x0A, 0x0A, 0x0A),
+    /* 0x27 */ (0x0E, 0x0A, 0x12),
+    /* 0x28 */ (0x01, 0x04, 0x09), // Left + A
+    /* 0x29 */ (0x01, 0x0B, 0x06),
+    /* 0x2A */ (0x01, 0x0A, 0x06),
+    /* 0x2B */ (0x0B, 0x0A, 0x01), // Up + A
+    /* 0x2C */ (0x10, 0x0A, 0x01), // The Legend of Zelda: Link's Awakening
+    /* 0x2D */ (0x01, 0x0A, 0x09),
+    /* 0x2E */ (0x0B, 0x01, 0x14),
+    /* 0x2F */ (0x01, 0x0A, 0x15),
+    /* 0x30 */ (0x01, 0x0B, 0x0A), // Left
+    /* 0x31 */ (0x0A, 0x0B, 0x0C), // Down + B
+    /* 0x32 */ (0x0B, 0x0B, 0x16),
+];
+
+// Checksums of titles licensed by Nintendo. Entries from FIRST_DUPLICATE_CHECKSUM onwards
+// collide with other titles and are matched against DUPLICATE_CHECKSUM_LETTERS
+#[rustfmt::skip]
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    // Duplicates
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+const FIRST_DUPLICATE_CHECKSUM: usize = 65;
+
+const DUPLICATE_CHECKSUM_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Index to PALETTE_COMBINATIONS for each entry of TITLE_CHECKSUMS
+#[rustfmt::skip]
+const PALETTE_PER_CHECKSUM: [u8; 94] = [
+    0x00, 0x04, 0x05, 0x23, 0x22, 0x03, 0x1F, 0x0F, 0x0A, 0x05, 0x13, 0x24, 0x07, 0x25, 0x1E, 0x2C,
+    0x15, 0x20, 0x1F, 0x14, 0x05, 0x21, 0x0D, 0x0E, 0x05, 0x1D, 0x05, 0x12, 0x09, 0x03, 0x02, 0x1A,
+    0x19, 0x19, 0x29, 0x2A, 0x1A, 0x2D, 0x2A, 0x2D, 0x24, 0x26, 0x1A, 0x2A, 0x1E, 0x29, 0x22, 0x22,
+    0x05, 0x2A, 0x06, 0x05, 0x21, 0x19, 0x2A, 0x2A, 0x28, 0x02, 0x10, 0x19, 0x2A, 0x2A, 0x05, 0x00,
+    0x27,
+    // Duplicates
+    0x24, 0x16, 0x19, 0x06, 0x20, 0x0C, 0x24, 0x0B, 0x27, 0x12, 0x27, 0x18, 0x1F, 0x32, 0x11, 0x2E,
+    0x06, 0x1B, 0x00, 0x2F, 0x29, 0x29, 0x00, 0x00, 0x13, 0x22, 0x17, 0x12, 0x1D,
+];
+
+fn combination(index: u8) -> CompatPalette {
+    let (obj0, obj1, bg) = PALETTE_COMBINATIONS[usize::from(index)];
+
+    CompatPalette {
+        bg: RAW_PALETTES[usize::from(bg)],
+        obj0: RAW_PALETTES[usize::from(obj0)],
+        obj1: RAW_PALETTES[usize::from(obj1)],
+    }
+}
+
+fn is_nintendo_licensed(header: &CartridgeHeader) -> bool {
+    header.lic_code_old == 0x01 || (header.lic_code_old == 0x33 && header.lic_code_new == *b"01")
+}
+
+fn manual_palette_index(dmg_palette: DmgPalette) -> Option<u8> {
+    match dmg_palette {
+        DmgPalette::Classic => None,
+        DmgPalette::Up => Some(0x05),
+        DmgPalette::UpA => Some(0x2B),
+        DmgPalette::UpB => Some(0x1C),
+        DmgPalette::Left => Some(0x30),
+        DmgPalette::LeftA => Some(0x28),
+        DmgPalette::LeftB => Some(0x07),
+        DmgPalette::Down => Some(0x08),
+        DmgPalette::DownA => Some(0x03),
+        DmgPalette::DownB => Some(0x31),
+        DmgPalette::Right => Some(0x01),
+        DmgPalette::RightA => Some(0x00),
+        DmgPalette::RightB => Some(0x06),
+    }
+}
+
+pub fn lookup_palette_index(header: &CartridgeHeader) -> u8 {
+    if !is_nintendo_licensed(header) {
+        return 0;
+    }
+
+    let checksum = header
+        .title
+        .iter()
+        .fold(0u8, |acc, byte| acc.wrapping_add(*byte));
+
+    let found = TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .find(|(index, title_checksum)| {
+            if **title_checksum != checksum {
+                return false;
+            }
+
+            *index < FIRST_DUPLICATE_CHECKSUM
+                || DUPLICATE_CHECKSUM_LETTERS[*index - FIRST_DUPLICATE_CHECKSUM] == header.title[3]
+        });
+
+    match found {
+        Some((index, _)) => PALETTE_PER_CHECKSUM[index],
+        None => 0,
+    }
+}
+
+pub fn compat_palette(
+    header: &CartridgeHeader,
+    comp_mode: CompatibilityMode,
+    dmg_palette: Option<DmgPalette>,
+) -> Option<CompatPalette> {
+    // CGB games use their own palettes and the SGB colorizes the screen through packets
+    if comp_mode == CompatibilityMode::ModeCgb || comp_mode == CompatibilityMode::ModeSgb {
+        return None;
+    }
+
+    match dmg_palette {
+        Some(manual) => manual_palette_index(manual).map(combination),
+        None if comp_mode == CompatibilityMode::ModeCgbDmg => {
+            Some(combination(lookup_palette_index(header)))
+        }
+        None => None,
+    }
+}
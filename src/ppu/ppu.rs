@@ -9,9 +9,12 @@ use crate::{
         soc::{self},
     },
     util::util,
-    CompatibilityMode, GbCtx,
+    CompatibilityMode, GbCtx, HardwareModel,
 };
 
+use super::{palettes::CompatPalette, viewer::PpuSnapshot};
+use crate::sgb::screen::{SgbFrameSender, SgbScreen};
+
 pub type FrameBuffer = [[u16; 160]; 144];
 pub type PpuFrameSender = std::sync::mpsc::SyncSender<FrameBuffer>;
 
@@ -32,6 +35,15 @@ const OAM_BIT_X_FLIP: u8 = 1 << 5;
 const OAM_BIT_CGB_BANK: u8 = 1 << 3;
 
 const CGB_BG_PRIO_BIT: u8 = 0x80;
+// Debug: BG or window pixel hidden, the mask keeps its colour for sprite priority
+const BG_HIDDEN_BIT: u8 = 0x40;
+
+const DMG_PALETTE_BG: u8 = 0;
+const DMG_PALETTE_OBJ0: u8 = 1;
+const DMG_PALETTE_OBJ1: u8 = 2;
+
+const DEBUG_WINDOW_COLOR: u16 = 0x03E0;
+const DEBUG_SPRITE_COLOR: u16 = 0x001F;
 
 macro_rules! read_write {
     ( $read_name:ident, $write_name:ident, $var_name:ident ) => {
@@ -54,6 +66,39 @@ pub enum PpuMode {
     PpuVBlank = 1,
 }
 
+// The image would never change at 1.0
+pub const MAX_PERSISTENCE: f32 = 0.9;
+
+// LCD ghosting, the frames sent through PpuFrameSender keep a share of the previous sent frame,
+// so changes fade out over several frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBlending {
+    Off,
+    // Persistence of the emulated model's LCD
+    Model,
+    // Share of the previous frame kept, from 0.0 (off) to MAX_PERSISTENCE
+    Custom(f32),
+}
+
+// CPU accesses to FE00-FEFF during OAM scan which corrupt OAM on DMG
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OamBugAccess {
+    Read,
+    Write,
+    // Read in the same M-cycle as a 16-bit increment/decrement, e.g. LD A,[HL+] or POP
+    ReadIncDec,
+}
+
+// Debug options that only change the rendered image, never the emulated timing
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PpuDebugFlags {
+    pub hide_bg: bool,
+    pub hide_window: bool,
+    pub hide_sprites: bool,
+    pub highlight_window: bool,
+    pub highlight_sprites: bool,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct Sprite {
     y: u8,
@@ -84,10 +129,19 @@ pub struct PPU {
     fetcher_x: u16,
     bg_scanline_mask: [u8; 160],
 
+    // DMG palette (BG, OBJ0, OBJ1) and shade of each pixel on the current scanline
+    dmg_scanline: [(u8, u8); 160],
+
     // @todo - 160*144=23040, allocate on the heaps
     rt: FrameBuffer,
     frame_chan: Option<PpuFrameSender>,
 
+    // Weight of the previous frame (0-256) and the previous frame itself
+    blend_persistence: u16,
+    blend_prev_frame: Option<Box<FrameBuffer>>,
+
+    sgb_frame_chan: Option<SgbFrameSender>,
+
     sync_video: bool,
 
     lcdc_enable: bool,
@@ -121,6 +175,14 @@ pub struct PPU {
     cgb_bg_palettes: [u8; 0x40],
     cgb_ob_palettes: [u8; 0x40],
 
+    // DMG game colorized through CGB palettes (BG0, OBJ0, OBJ1)
+    compat_palette: bool,
+
+    // Super Game Boy colorization
+    sgb_screen: Option<Box<SgbScreen>>,
+
+    debug_flags: PpuDebugFlags,
+
     // CGB registers
     vbk: bool,
     opri: bool,
@@ -151,16 +213,31 @@ impl Display for PPU {
 impl PPU {
     pub fn new(
         frame_chan: Option<PpuFrameSender>,
+        sgb_frame_chan: Option<SgbFrameSender>,
         sync_video: bool,
+        frame_blending: FrameBlending,
         ctx: std::rc::Rc<GbCtx>,
     ) -> Self {
         let cgb = ctx.cgb;
+        let ctx_sgb = ctx.comp_mode == CompatibilityMode::ModeSgb;
+
+        let persistence = match frame_blending {
+            FrameBlending::Off => 0.0,
+            FrameBlending::Model => PPU::lcd_persistence(ctx.model),
+            FrameBlending::Custom(persistence) => persistence.clamp(0.0, MAX_PERSISTENCE),
+        };
+        let blend_persistence = (persistence * 256.0) as u16;
+
         Self {
             ctx,
             frame_chan,
+            sgb_frame_chan,
+            blend_persistence,
+            blend_prev_frame: None,
             sync_video,
             draw_window: false,
             bg_scanline_mask: [0; 160],
+            dmg_scanline: [(DMG_PALETTE_BG, 0); 160],
             window_line_counter: 0,
             fetcher_x: 0,
             cycles_mode: CYCLES_PER_OAM_SCAN,
@@ -198,6 +275,13 @@ impl PPU {
             opri: !cgb,
             cgb_bg_palettes: [0xFF; 0x40],
             cgb_ob_palettes: [0xFF; 0x40],
+            compat_palette: false,
+            sgb_screen: if ctx_sgb {
+                Some(Box::new(SgbScreen::new()))
+            } else {
+                None
+            },
+            debug_flags: PpuDebugFlags::default(),
             bcps: 0x88,
             ocps: 0x90,
             hblank_cycle: false,
@@ -209,10 +293,135 @@ impl PPU {
         &self.rt
     }
 
+    pub fn load_compat_palette(&mut self, compat_palette: &CompatPalette) {
+        PPU::write_palette(&mut self.cgb_bg_palettes, 0, &compat_palette.bg);
+        PPU::write_palette(&mut self.cgb_ob_palettes, 0, &compat_palette.obj0);
+        PPU::write_palette(&mut self.cgb_ob_palettes, 1, &compat_palette.obj1);
+
+        self.compat_palette = true;
+    }
+
+    pub fn get_debug_flags(&self) -> PpuDebugFlags {
+        self.debug_flags
+    }
+
+    pub fn set_debug_flags(&mut self, debug_flags: PpuDebugFlags) {
+        self.debug_flags = debug_flags;
+    }
+
+    pub fn get_vram(&self) -> &[u8; 0x4000] {
+        &self.vram
+    }
+
+    pub fn get_oam(&self) -> &[u8; 0xA0] {
+        &self.oam
+    }
+
+    pub fn get_cgb_bg_palettes(&self) -> &[u8; 0x40] {
+        &self.cgb_bg_palettes
+    }
+
+    pub fn get_cgb_ob_palettes(&self) -> &[u8; 0x40] {
+        &self.cgb_ob_palettes
+    }
+
+    pub fn snapshot(&self) -> PpuSnapshot {
+        let mut bg_palettes = [[0; 4]; 8];
+        let mut obj_palettes = [[0; 4]; 8];
+
+        if self.ctx.cgb {
+            for palette in 0..8 {
+                for color in 0..4 {
+                    bg_palettes[palette][color] =
+                        PPU::get_cgb_color(&self.cgb_bg_palettes, palette as u8, color as u8);
+                    obj_palettes[palette][color] =
+                        PPU::get_cgb_color(&self.cgb_ob_palettes, palette as u8, color as u8);
+                }
+            }
+        } else {
+            let dmg_colors = |cgb_palettes: &[u8; 64], cgb_palette: u8, dmg_palette: u8| {
+                let mut colors = [0; 4];
+                for (color_index, color) in colors.iter_mut().enumerate() {
+                    let dmg_color = (dmg_palette >> (color_index * 2)) & 0x3;
+                    *color = if self.compat_palette {
+                        PPU::get_cgb_color(cgb_palettes, cgb_palette, dmg_color)
+                    } else {
+                        PPU::get_dmg_color(dmg_color)
+                    };
+                }
+                colors
+            };
+
+            bg_palettes[0] = dmg_colors(&self.cgb_bg_palettes, 0, self.bgp);
+            obj_palettes[0] = dmg_colors(&self.cgb_ob_palettes, 0, self.obp0);
+            obj_palettes[1] = dmg_colors(&self.cgb_ob_palettes, 1, self.obp1);
+        }
+
+        PpuSnapshot {
+            vram: self.vram.clone(),
+            oam: self.oam.clone(),
+            bg_palettes,
+            obj_palettes,
+            cgb: self.ctx.cgb,
+            lcdc: self.read_lcdc(),
+            scx: self.scx,
+            scy: self.scy,
+        }
+    }
+
+    pub fn get_sgb_screen(&mut self) -> Option<&mut SgbScreen> {
+        self.sgb_screen.as_deref_mut()
+    }
+
+    // The SGB reads transferred data from the displayed screen: the first 256 tiles
+    // of the BG map (20 per line) using the current tile data addressing mode
+    pub fn read_sgb_transfer(&self) -> Box<[u8; 0x1000]> {
+        let mut data: Box<[u8; 0x1000]> = vec![0; 0x1000].into_boxed_slice().try_into().unwrap();
+
+        let tilemap_addr = if self.lcdc_bg_tilemap {
+            ADDR_TILEMAP_9C00
+        } else {
+            ADDR_TILEMAP_9800
+        };
+
+        for (index, tile_data) in data.chunks_exact_mut(16).enumerate() {
+            let tile_index = (index / 20) * 32 + (index % 20);
+            let tile_number = self.read_vram_banked(false, tilemap_addr + tile_index as u16);
+
+            let tile_base = if self.lcdc_bg_wnd_tiles {
+                u16::from(tile_number) * 16
+            } else {
+                0x1000_u16.wrapping_add_signed(i16::from(tile_number as i8) * 16)
+            };
+
+            for (offset, byte) in tile_data.iter_mut().enumerate() {
+                *byte = self.read_vram_banked(false, tile_base + offset as u16);
+            }
+        }
+
+        data
+    }
+
     pub fn get_hblank_cycle(&self) -> bool {
         self.hblank_cycle
     }
 
+    pub fn in_hblank(&self) -> bool {
+        self.stat_mode == PpuMode::PpuHBlank
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc_enable
+    }
+
+    // Starts at the beginning of a VBlank line instead of the first line
+    pub fn boot_in_vblank(&mut self, ly: u8) {
+        self.ly = ly;
+        self.stat_mode = PpuMode::PpuVBlank;
+        self.cycles_mode = CYCLES_PER_VBLANK_LINE;
+        self.update_lyc_eq_ly();
+    }
+
     pub fn reset(&mut self) {
         self.ly = 0;
 
@@ -226,20 +435,89 @@ impl PPU {
         self.stat_mode = PpuMode::PpuHBlank;
     }
 
+    // Approximate share of the previous frame still visible due to slow LCD response
+    fn lcd_persistence(model: HardwareModel) -> f32 {
+        match model {
+            HardwareModel::Dmg0 | HardwareModel::Dmg => 0.5,
+            HardwareModel::Mgb | HardwareModel::Cgb | HardwareModel::CgbE => 0.35,
+            HardwareModel::Agb => 0.2,
+            // Displayed on a TV
+            HardwareModel::Sgb | HardwareModel::Sgb2 => 0.0,
+        }
+    }
+
+    fn blend_frame(&mut self) -> FrameBuffer {
+        let mut frame = self.rt;
+
+        if let Some(prev_frame) = &self.blend_prev_frame {
+            let prev_weight = u32::from(self.blend_persistence);
+            let cur_weight = 256 - prev_weight;
+
+            for (line, prev_line) in frame.iter_mut().zip(prev_frame.iter()) {
+                for (pixel, prev_pixel) in line.iter_mut().zip(prev_line.iter()) {
+                    let mut blended = 0;
+                    for shift in [0, 5, 10] {
+                        let cur = u32::from(*pixel >> shift) & 0x1F;
+                        let prev = u32::from(*prev_pixel >> shift) & 0x1F;
+                        let channel = (cur * cur_weight + prev * prev_weight + 128) >> 8;
+                        blended |= (channel as u16) << shift;
+                    }
+                    *pixel = blended;
+                }
+            }
+        }
+
+        // Blended output is fed back, which makes old frames decay instead of vanishing
+        match &mut self.blend_prev_frame {
+            Some(prev_frame) => **prev_frame = frame,
+            None => self.blend_prev_frame = Some(Box::new(frame)),
+        }
+
+        frame
+    }
+
+    // The LCD is blank while the CPU is in STOP mode, frames are still sent to keep the pacing
+    pub fn stopped_frame(&mut self, ctx: &mut soc::ClockContext) {
+        self.rt = [[0x7FFF; 160]; 144];
+
+        if self.vsync() {
+            ctx.set_events(soc::SocEventBits::SocEventsVSyncAndExit);
+        }
+        ctx.set_events(soc::SocEventBits::SocEventVSync);
+    }
+
     // vsync can have a big stack frame due to copying over framebuffer
     // never inline to avoid paying stack probes unless necessary
     #[inline(never)]
-    pub fn vsync(&self) -> bool {
+    pub fn vsync(&mut self) -> bool {
+        let frame = if self.blend_persistence != 0 && self.frame_chan.is_some() {
+            self.blend_frame()
+        } else {
+            self.rt
+        };
+
+        // Sent ahead of the Game Boy frame, so the frontend can pick it up right after
+        if let (Some(sgb_frame_chan), Some(sgb_screen)) =
+            (&self.sgb_frame_chan, &mut self.sgb_screen)
+        {
+            match sgb_frame_chan.try_send(*sgb_screen.compose_frame(&frame)) {
+                Ok(_) | Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => {
+                    return true;
+                }
+            }
+        }
+
         if let Some(frame_chan) = &self.frame_chan {
             if self.sync_video {
-                match frame_chan.send(self.rt) {
+                match frame_chan.send(frame) {
                     Ok(_) => {}
                     Err(_err) => {
                         return true;
                     }
                 }
             } else {
-                match frame_chan.try_send(self.rt) {
+                match frame_chan.try_send(frame) {
                     Ok(_) | Err(TrySendError::Full(_)) => {}
                     Err(TrySendError::Disconnected(_)) => {
                         return true;
@@ -295,10 +573,11 @@ impl PPU {
                 self.fetcher_x = 0;
                 self.cycles_mode = 94 - self.draw_length;
                 self.stat_mode = PpuMode::PpuHBlank;
+
+                // Signals the start of HBlank for HBlank DMA
+                self.hblank_cycle = true;
             }
             PpuMode::PpuHBlank => {
-                // @todo - Better integrated handling for hdma
-                self.hblank_cycle = true;
                 self.ly += 1;
                 self.update_lyc_eq_ly();
 
@@ -431,6 +710,54 @@ impl PPU {
         self.oam[(addr & 0xFF) as usize] = data;
     }
 
+    // OAM corruption bug, the CPU access collides with the row of 8 bytes the PPU is reading.
+    // The first row is never corrupted.
+    // https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+    pub fn oam_bug(&mut self, access: OamBugAccess) {
+        if !self.lcdc_enable || self.stat_mode != PpuMode::PpuOamScan {
+            return;
+        }
+
+        let row = usize::from(CYCLES_PER_OAM_SCAN - self.cycles_mode).min(19);
+        if row == 0 {
+            return;
+        }
+
+        if access == OamBugAccess::ReadIncDec && (4..19).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            self.oam.copy_within((row - 1) * 8..row * 8, row * 8);
+            self.oam.copy_within((row - 1) * 8..row * 8, (row - 2) * 8);
+        }
+
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+
+        let corrupted = match access {
+            OamBugAccess::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBugAccess::Read | OamBugAccess::ReadIncDec => b | (a & c),
+        };
+
+        self.set_oam_word(row, 0, corrupted);
+        self.oam
+            .copy_within((row - 1) * 8 + 2..row * 8, row * 8 + 2);
+    }
+
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let addr = row * 8 + word * 2;
+        u16::from_le_bytes([self.oam[addr], self.oam[addr + 1]])
+    }
+
+    fn set_oam_word(&mut self, row: usize, word: usize, data: u16) {
+        let addr = row * 8 + word * 2;
+        self.oam[addr..addr + 2].copy_from_slice(&data.to_le_bytes());
+    }
+
     pub fn read_vram(&self, addr: u16) -> u8 {
         return match self.stat_mode {
             PpuMode::PpuDraw => 0xFF,
@@ -448,7 +775,7 @@ impl PPU {
     }
 
     pub fn read_vbk(&self) -> u8 {
-        if self.ctx.comp_mode != CompatibilityMode::ModeDmg {
+        if self.ctx.comp_mode.is_cgb_hardware() {
             (self.vbk as u8) | 0xFE
         } else {
             0xFF
@@ -462,7 +789,7 @@ impl PPU {
     }
 
     pub fn read_bcps(&self) -> u8 {
-        if self.ctx.comp_mode == CompatibilityMode::ModeDmg {
+        if !self.ctx.comp_mode.is_cgb_hardware() {
             return 0xFF;
         }
         return self.bcps | 0x40;
@@ -506,7 +833,7 @@ impl PPU {
     }
 
     pub fn read_ocps(&self) -> u8 {
-        if self.ctx.comp_mode == CompatibilityMode::ModeDmg {
+        if !self.ctx.comp_mode.is_cgb_hardware() {
             return 0xFF;
         }
         return self.ocps | 0x40;
@@ -520,7 +847,7 @@ impl PPU {
     }
 
     pub fn read_ocpd(&self) -> u8 {
-        if self.ctx.comp_mode == CompatibilityMode::ModeDmg {
+        if !self.ctx.comp_mode.is_cgb_hardware() {
             return 0xFF;
         }
 
@@ -721,6 +1048,14 @@ impl PPU {
         palette_color
     }
 
+    fn write_palette(palettes: &mut [u8; 64], palette: usize, colors: &[u16; 4]) {
+        for (color_index, color) in colors.iter().enumerate() {
+            let palette_index = (palette * 4 * 2) + (color_index * 2);
+            palettes[palette_index] = util::get_low(*color);
+            palettes[palette_index + 1] = util::get_high(*color);
+        }
+    }
+
     fn get_dmg_color(dmg_color: u8) -> u16 {
         const INTENSITY: f64 = 8.22580;
 
@@ -742,13 +1077,37 @@ impl PPU {
         DMG_PALETTE[(dmg_color & 0x3) as usize]
     }
 
+    fn output_dmg_scanline(&mut self) {
+        let ly = self.ly as usize;
+
+        if let Some(sgb_screen) = &self.sgb_screen {
+            sgb_screen.output_scanline(ly, &self.dmg_scanline, &mut self.rt[ly]);
+            return;
+        }
+
+        for x in 0..160 {
+            let (dmg_palette, dmg_color) = self.dmg_scanline[x];
+
+            self.rt[ly][x] = if self.compat_palette {
+                // CGB in DMG compatibility mode: BGP, OBP0 and OBP1 index BG0, OBJ0 and OBJ1
+                match dmg_palette {
+                    DMG_PALETTE_BG => PPU::get_cgb_color(&self.cgb_bg_palettes, 0, dmg_color),
+                    DMG_PALETTE_OBJ0 => PPU::get_cgb_color(&self.cgb_ob_palettes, 0, dmg_color),
+                    _ => PPU::get_cgb_color(&self.cgb_ob_palettes, 1, dmg_color),
+                }
+            } else {
+                PPU::get_dmg_color(dmg_color)
+            };
+        }
+    }
+
     fn draw_background(&mut self) {
         self.fetcher_x = 0;
 
         if !self.ctx.cgb && !self.lcdc_bit_0 {
             for x in 0..160 {
                 self.bg_scanline_mask[x] = 0;
-                self.rt[self.ly as usize][x as usize] = PPU::get_dmg_color(0);
+                self.dmg_scanline[x] = (DMG_PALETTE_BG, 0);
             }
             return;
         }
@@ -789,12 +1148,19 @@ impl PPU {
                     self.bg_scanline_mask[x] |= CGB_BG_PRIO_BIT;
                 }
 
+                let bg_pixel = if self.debug_flags.hide_bg {
+                    self.bg_scanline_mask[x] |= BG_HIDDEN_BIT;
+                    0
+                } else {
+                    bg_pixel
+                };
+
                 if self.ctx.cgb {
                     rt_scanline[x] =
                         PPU::get_cgb_color(&self.cgb_bg_palettes, cgb_attrs & 0x7, bg_pixel);
                 } else {
                     let palette_color = (self.bgp >> (bg_pixel * 2)) & 0x3;
-                    rt_scanline[x] = PPU::get_dmg_color(palette_color);
+                    self.dmg_scanline[x] = (DMG_PALETTE_BG, palette_color);
                 }
 
                 x += 1;
@@ -863,12 +1229,19 @@ impl PPU {
                     self.bg_scanline_mask[x] |= CGB_BG_PRIO_BIT;
                 }
 
+                let win_pixel = if self.debug_flags.hide_window {
+                    self.bg_scanline_mask[x] |= BG_HIDDEN_BIT;
+                    0
+                } else {
+                    win_pixel
+                };
+
                 if self.ctx.cgb {
                     rt_scanline[x] =
                         PPU::get_cgb_color(&self.cgb_bg_palettes, cgb_attrs & 0x7, win_pixel);
                 } else {
                     let palette_color = (self.bgp >> (win_pixel * 2)) & 0x3;
-                    rt_scanline[x] = PPU::get_dmg_color(palette_color);
+                    self.dmg_scanline[x] = (DMG_PALETTE_BG, palette_color);
                 }
 
                 x += 1;
@@ -886,7 +1259,7 @@ impl PPU {
     }
 
     fn draw_sprites(&mut self) {
-        if !self.lcdc_obj_enable {
+        if !self.lcdc_obj_enable || self.debug_flags.hide_sprites {
             return;
         }
 
@@ -941,9 +1314,14 @@ impl PPU {
                     // back to the framebuffer to emulate this
                     // @todo - Check this
                     if !self.ctx.cgb {
-                        let bg_clr = self.bg_scanline_mask[x as usize] & 0x7;
+                        let bg_mask = self.bg_scanline_mask[x as usize];
+                        let bg_clr = if bg_mask & BG_HIDDEN_BIT != 0 {
+                            0
+                        } else {
+                            bg_mask & 0x3
+                        };
                         let bg_pixel = (self.bgp >> (bg_clr * 2)) & 0x3;
-                        self.rt[self.ly as usize][x as usize] = PPU::get_dmg_color(bg_pixel);
+                        self.dmg_scanline[x as usize] = (DMG_PALETTE_BG, bg_pixel);
                     } else {
                         // @todo CGB: bg-over-obj masking
                     }
@@ -955,14 +1333,48 @@ impl PPU {
                     self.rt[self.ly as usize][x as usize] =
                         PPU::get_cgb_color(&self.cgb_ob_palettes, sprite.attr & 0x7, sprite_color);
                 } else {
-                    let sprite_palette = if sprite.attr & (1 << 4) == 0 {
-                        self.obp0
+                    let (sprite_palette, dmg_palette) = if sprite.attr & (1 << 4) == 0 {
+                        (self.obp0, DMG_PALETTE_OBJ0)
                     } else {
-                        self.obp1
+                        (self.obp1, DMG_PALETTE_OBJ1)
                     };
 
                     let palette_color = (sprite_palette >> (sprite_color * 2)) & 0x3;
-                    self.rt[self.ly as usize][x as usize] = PPU::get_dmg_color(palette_color);
+                    self.dmg_scanline[x as usize] = (dmg_palette, palette_color);
+                }
+            }
+        }
+    }
+
+    fn draw_debug_highlights(&mut self, window_pos: Option<u8>) {
+        fn blend(color: u16, highlight: u16) -> u16 {
+            ((color & 0x7BDE) >> 1) + ((highlight & 0x7BDE) >> 1)
+        }
+
+        let ly = self.ly as usize;
+
+        if self.debug_flags.highlight_window {
+            if let Some(wx_sub7) = window_pos {
+                for pixel in self.rt[ly][wx_sub7 as usize..].iter_mut() {
+                    *pixel = blend(*pixel, DEBUG_WINDOW_COLOR);
+                }
+            }
+        }
+
+        if self.debug_flags.highlight_sprites && self.lcdc_obj_enable {
+            let obj_height: u8 = if self.lcdc_obj_size { 16 } else { 8 };
+
+            for sprite in self.sprite_buffer.iter() {
+                let sprite_y = (self.ly + 16).wrapping_sub(sprite.y);
+                let left = i16::from(sprite.x) - 8;
+
+                // Full line at the top and bottom of the bounding box, edges in between
+                let full_line = sprite_y == 0 || sprite_y == obj_height - 1;
+
+                for x in left..left + 8 {
+                    if (full_line || x == left || x == left + 7) && (0..160).contains(&x) {
+                        self.rt[ly][x as usize] = DEBUG_SPRITE_COLOR;
+                    }
                 }
             }
         }
@@ -1028,6 +1440,12 @@ impl PPU {
         let window_pos = self.draw_window();
         self.draw_sprites();
 
+        if !self.ctx.cgb {
+            self.output_dmg_scanline();
+        }
+
+        self.draw_debug_highlights(window_pos);
+
         self.calc_mode3_len(window_pos)
     }
 
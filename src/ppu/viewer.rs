@@ -0,0 +1,232 @@
+// Read-only views of the PPU memory for debugging graphics
+
+pub type PpuViewerSender = std::sync::mpsc::SyncSender<Box<PpuSnapshot>>;
+
+// Color used for overlays (scroll viewport) and transparent sprite pixels
+const OVERLAY_COLOR: u16 = 0x001F;
+const TRANSPARENT_COLOR: u16 = 0x4210;
+
+const TILES_PER_BANK: usize = 384;
+const TILES_PER_ROW: usize = 16;
+
+// Image in the same RGB555 format as the framebuffer
+pub struct PpuImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u16>,
+}
+
+impl PpuImage {
+    fn new(width: usize, height: usize, color: u16) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * self.width + x]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
+        self.pixels[y * self.width + x] = color;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OamEntry {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    // DMG: OBP0/OBP1, CGB: OBJ palette 0-7
+    pub palette: u8,
+    pub bank: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    pub bg_priority: bool,
+}
+
+pub struct PpuSnapshot {
+    pub vram: Box<[u8; 0x4000]>,
+    pub oam: Box<[u8; 0xA0]>,
+    // Colors as displayed. On DMG, BGP, OBP0 and OBP1 are resolved into BG0, OBJ0 and OBJ1
+    pub bg_palettes: [[u16; 4]; 8],
+    pub obj_palettes: [[u16; 4]; 8],
+    pub cgb: bool,
+    pub lcdc: u8,
+    pub scx: u8,
+    pub scy: u8,
+}
+
+impl PpuSnapshot {
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.oam
+            .chunks_exact(4)
+            .map(|obj| {
+                let attr = obj[3];
+                OamEntry {
+                    y: obj[0],
+                    x: obj[1],
+                    tile: obj[2],
+                    palette: if self.cgb {
+                        attr & 0x7
+                    } else {
+                        (attr >> 4) & 0x1
+                    },
+                    bank: if self.cgb { (attr >> 3) & 0x1 } else { 0 },
+                    x_flip: attr & 0x20 != 0,
+                    y_flip: attr & 0x40 != 0,
+                    bg_priority: attr & 0x80 != 0,
+                }
+            })
+            .collect()
+    }
+
+    // All tiles of both VRAM banks side by side, 16 tiles per row
+    pub fn render_tiles(&self) -> PpuImage {
+        let bank_width = TILES_PER_ROW * 8;
+        let bank_height = TILES_PER_BANK / TILES_PER_ROW * 8;
+        let mut img = PpuImage::new(bank_width * 2, bank_height, 0);
+
+        for bank in 0..2 {
+            for tile in 0..TILES_PER_BANK {
+                let tile_x = bank * bank_width + (tile % TILES_PER_ROW) * 8;
+                let tile_y = (tile / TILES_PER_ROW) * 8;
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let color = self.tile_pixel(bank, tile * 16, x, y);
+                        img.set_pixel(tile_x + x, tile_y + y, self.bg_palettes[0][color]);
+                    }
+                }
+            }
+        }
+
+        img
+    }
+
+    // 32x32 tilemap at 9800 or 9C00, the BG viewport is overlaid when the map is in use
+    pub fn render_tilemap(&self, tilemap_9c00: bool) -> PpuImage {
+        let mut img = PpuImage::new(256, 256, 0);
+        let map_base = if tilemap_9c00 { 0x1C00 } else { 0x1800 };
+
+        for map_y in 0..32 {
+            for map_x in 0..32 {
+                let map_addr = map_base + map_y * 32 + map_x;
+                let tile = self.vram[map_addr];
+                let attr = if self.cgb {
+                    self.vram[0x2000 + map_addr]
+                } else {
+                    0
+                };
+
+                let tile_addr = if self.lcdc & 0x10 != 0 {
+                    usize::from(tile) * 16
+                } else {
+                    (0x1000 + i32::from(tile as i8) * 16) as usize
+                };
+
+                let palette = &self.bg_palettes[usize::from(attr & 0x7)];
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let tile_x = if attr & 0x20 != 0 { 7 - x } else { x };
+                        let tile_y = if attr & 0x40 != 0 { 7 - y } else { y };
+                        let bank = usize::from((attr >> 3) & 0x1);
+
+                        let color = self.tile_pixel(bank, tile_addr, tile_x, tile_y);
+                        img.set_pixel(map_x * 8 + x, map_y * 8 + y, palette[color]);
+                    }
+                }
+            }
+        }
+
+        if (self.lcdc & 0x08 != 0) == tilemap_9c00 {
+            self.overlay_viewport(&mut img);
+        }
+
+        img
+    }
+
+    // All 40 objects, 8 per row. Each cell is 8x16 to fit tall objects
+    pub fn render_sprites(&self) -> PpuImage {
+        let mut img = PpuImage::new(8 * 8, 5 * 16, TRANSPARENT_COLOR);
+        let obj_height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+
+        for (index, obj) in self.oam_entries().iter().enumerate() {
+            let cell_x = (index % 8) * 8;
+            let cell_y = (index / 8) * 16;
+            let tile = if obj_height == 16 {
+                obj.tile & 0xFE
+            } else {
+                obj.tile
+            };
+            let palette = &self.obj_palettes[usize::from(obj.palette)];
+
+            for y in 0..obj_height {
+                for x in 0..8 {
+                    let tile_x = if obj.x_flip { 7 - x } else { x };
+                    let tile_y = if obj.y_flip { obj_height - 1 - y } else { y };
+                    let tile_addr = usize::from(tile) * 16;
+
+                    let color = self.tile_pixel(usize::from(obj.bank), tile_addr, tile_x, tile_y);
+                    if color != 0 {
+                        img.set_pixel(cell_x + x, cell_y + y, palette[color]);
+                    }
+                }
+            }
+        }
+
+        img
+    }
+
+    // BG palettes on the left, OBJ palettes on the right, one palette per row
+    pub fn render_palettes(&self) -> PpuImage {
+        const SWATCH_WIDTH: usize = 32;
+        const SWATCH_HEIGHT: usize = 8;
+
+        let mut img = PpuImage::new(SWATCH_WIDTH * 4 * 2, SWATCH_HEIGHT * 8, 0);
+
+        for (column, palettes) in [&self.bg_palettes, &self.obj_palettes].iter().enumerate() {
+            for (palette_index, palette) in palettes.iter().enumerate() {
+                for (color_index, color) in palette.iter().enumerate() {
+                    let swatch_x = column * SWATCH_WIDTH * 4 + color_index * SWATCH_WIDTH;
+                    let swatch_y = palette_index * SWATCH_HEIGHT;
+
+                    for y in 0..SWATCH_HEIGHT {
+                        for x in 0..SWATCH_WIDTH {
+                            img.set_pixel(swatch_x + x, swatch_y + y, *color);
+                        }
+                    }
+                }
+            }
+        }
+
+        img
+    }
+
+    // Color index (0-3) of a pixel in the tile at tile_addr, 8x16 objects continue into the next tile
+    fn tile_pixel(&self, bank: usize, tile_addr: usize, x: usize, y: usize) -> usize {
+        let addr = bank * 0x2000 + ((tile_addr + y * 2) & 0x1FFF);
+        let lb = (self.vram[addr] >> (7 - x)) & 0x1;
+        let hb = (self.vram[addr + 1] >> (7 - x)) & 0x1;
+
+        usize::from(lb | (hb << 1))
+    }
+
+    fn overlay_viewport(&self, img: &mut PpuImage) {
+        let scx = usize::from(self.scx);
+        let scy = usize::from(self.scy);
+
+        for x in 0..160 {
+            img.set_pixel((scx + x) % 256, scy, OVERLAY_COLOR);
+            img.set_pixel((scx + x) % 256, (scy + 143) % 256, OVERLAY_COLOR);
+        }
+
+        for y in 0..144 {
+            img.set_pixel(scx, (scy + y) % 256, OVERLAY_COLOR);
+            img.set_pixel((scx + 159) % 256, (scy + y) % 256, OVERLAY_COLOR);
+        }
+    }
+}
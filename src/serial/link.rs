@@ -0,0 +1,467 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::SerialConnector;
+
+pub const LINK_DEFAULT_PORT: u16 = 5657;
+// Other interfaces have to be chosen explicitly, the link is unauthenticated
+pub const LINK_DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// Normal speed M-cycles between two synchronizations, one bit at the 8192 Hz serial clock.
+// Counted in double speed M-cycles, which take half as long
+const SYNC_CYCLES: u32 = 128;
+const SYNC_HALF_CYCLES: u32 = SYNC_CYCLES * 2;
+
+// Waiting for the other side returns to the emulator this often, so it can still exit
+const SYNC_POLL: Duration = Duration::from_millis(100);
+// Reported as stalled when the other side doesn't answer for this long
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+// A lost connection is tried again this often when joining
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Packet: flags, byte clocked out by the sender, SB of the sender while it waits on the external
+// clock
+const PACKET_SIZE: usize = 3;
+const FLAG_TRANSFER: u8 = 1 << 0;
+const FLAG_READY: u8 = 1 << 1;
+const FLAG_DONE: u8 = 1 << 2;
+
+// Link cable to another emulator over TCP. Both sides exchange a packet every SYNC_CYCLES and wait
+// for each other's packet, which keeps them in lockstep. Every sync is a blocking round trip on
+// the emulator thread, so this is meant for loopback or a fast local network.
+// The side on the internal clock sends its byte with the first sync of the transfer and gets the
+// SB of the other side from the same sync, or 0xFF if it isn't waiting on the external clock.
+// Once all bits are shifted it sends a done flag, and both sides finish on that sync.
+// The connection outlives the Game Boy, see share, and is established again when it's lost
+pub struct TcpLink {
+    connection: Arc<Mutex<TcpConnection>>,
+    half_cycles: u32,
+
+    // Flags of the packet waiting for an answer
+    sent: u8,
+
+    // Transfer clocked by this side: byte to send, answer from the other side, done flag state
+    transfer_out: Option<u8>,
+    reply_in: Option<u8>,
+    finishing: bool,
+    finished: bool,
+
+    // Transfer clocked by the other side: SB while waiting on the external clock, byte received
+    // at the start of the transfer, byte handed over when the other side is done
+    ready: Option<u8>,
+    pending_in: Option<u8>,
+    transfer_in: Option<u8>,
+}
+
+enum Endpoint {
+    // Accepts the other side again, without blocking
+    Host(TcpListener),
+    Join(Vec<SocketAddr>),
+}
+
+struct TcpConnection {
+    endpoint: Endpoint,
+    stream: Option<TcpStream>,
+    last_attempt: Instant,
+
+    // A packet was sent and its answer is partially received
+    awaiting: bool,
+    recv: [u8; PACKET_SIZE],
+    recv_len: usize,
+    waiting_since: Instant,
+    stalled: bool,
+}
+
+impl TcpLink {
+    // Waits until the other emulator joins
+    pub fn host(address: impl ToSocketAddrs) -> io::Result<Self> {
+        TcpLink::accept(TcpListener::bind(address)?)
+    }
+
+    pub fn accept(listener: TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        listener.set_nonblocking(true)?;
+
+        TcpLink::new(Endpoint::Host(listener), stream)
+    }
+
+    pub fn join(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        let stream = TcpStream::connect(&addresses[..])?;
+
+        TcpLink::new(Endpoint::Join(addresses), stream)
+    }
+
+    fn new(endpoint: Endpoint, stream: TcpStream) -> io::Result<Self> {
+        let mut connection = TcpConnection {
+            endpoint,
+            stream: None,
+            last_attempt: Instant::now(),
+            awaiting: false,
+            recv: [0; PACKET_SIZE],
+            recv_len: 0,
+            waiting_since: Instant::now(),
+            stalled: false,
+        };
+        connection.connected(stream)?;
+
+        Ok(TcpLink::with_connection(Arc::new(Mutex::new(connection))))
+    }
+
+    fn with_connection(connection: Arc<Mutex<TcpConnection>>) -> Self {
+        Self {
+            connection,
+            half_cycles: 0,
+            sent: 0,
+            transfer_out: None,
+            reply_in: None,
+            finishing: false,
+            finished: false,
+            ready: None,
+            pending_in: None,
+            transfer_in: None,
+        }
+    }
+
+    // Same connection without a transfer in progress, for the next game after a reload. The
+    // other side waits while no Game Boy is plugged in
+    pub fn share(&self) -> TcpLink {
+        TcpLink::with_connection(self.connection.clone())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.lock().unwrap().stream.is_some()
+    }
+
+    fn sync(&mut self) {
+        let connection = self.connection.clone();
+        let mut connection = connection.lock().unwrap();
+
+        if !connection.is_connected() {
+            self.line_down();
+            return;
+        }
+
+        if !connection.awaiting {
+            let mut packet = [0u8; PACKET_SIZE];
+            if let Some(byte) = self.transfer_out.take() {
+                packet[0] |= FLAG_TRANSFER;
+                packet[1] = byte;
+            }
+            if let Some(byte) = self.ready {
+                packet[0] |= FLAG_READY;
+                packet[2] = byte;
+            }
+            if self.finishing {
+                packet[0] |= FLAG_DONE;
+            }
+            self.sent = packet[0];
+
+            if let Err(err) = connection.send(&packet) {
+                connection.disconnect(err);
+                self.line_down();
+                return;
+            }
+        }
+
+        match connection.receive() {
+            Ok(Some(packet)) => self.received(packet),
+            Ok(None) => {}
+            Err(err) => {
+                connection.disconnect(err);
+                self.line_down();
+            }
+        }
+    }
+
+    fn received(&mut self, packet: [u8; PACKET_SIZE]) {
+        if self.sent & FLAG_TRANSFER != 0 {
+            let waiting = packet[0] & FLAG_READY != 0;
+            self.reply_in = Some(if waiting { packet[2] } else { 0xFF });
+        }
+        if self.sent & FLAG_DONE != 0 {
+            self.finishing = false;
+            self.finished = true;
+        }
+
+        if packet[0] & FLAG_TRANSFER != 0 && self.sent & FLAG_READY != 0 {
+            self.pending_in = Some(packet[1]);
+        }
+        if packet[0] & FLAG_DONE != 0 {
+            self.transfer_in = self.pending_in.take();
+        }
+
+        self.sent = 0;
+    }
+
+    // Nothing drives the line while disconnected
+    fn line_down(&mut self) {
+        if self.transfer_out.take().is_some() || self.sent & FLAG_TRANSFER != 0 {
+            self.reply_in = Some(0xFF);
+        }
+        self.sent = 0;
+        self.finishing = false;
+        self.pending_in = None;
+        self.transfer_in = None;
+    }
+}
+
+impl TcpConnection {
+    fn connected(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(SYNC_POLL))?;
+
+        self.stream = Some(stream);
+        self.awaiting = false;
+        self.recv_len = 0;
+        self.stalled = false;
+
+        Ok(())
+    }
+
+    // Tries to connect again if the connection was lost
+    fn is_connected(&mut self) -> bool {
+        if self.stream.is_some() {
+            return true;
+        }
+
+        let stream = match &self.endpoint {
+            Endpoint::Host(listener) => listener.accept().map(|(stream, _)| stream),
+            Endpoint::Join(addresses) => {
+                if self.last_attempt.elapsed() < RECONNECT_INTERVAL {
+                    return false;
+                }
+                self.last_attempt = Instant::now();
+
+                addresses
+                    .iter()
+                    .map(|address| TcpStream::connect_timeout(address, SYNC_POLL))
+                    .find(|stream| stream.is_ok())
+                    .unwrap_or_else(|| Err(ErrorKind::NotConnected.into()))
+            }
+        };
+
+        match stream.and_then(|stream| self.connected(stream)) {
+            Ok(()) => {
+                println!("Link cable connected again");
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn send(&mut self, packet: &[u8; PACKET_SIZE]) -> io::Result<()> {
+        if let Some(stream) = &mut self.stream {
+            stream.write_all(packet)?;
+        }
+
+        self.awaiting = true;
+        self.waiting_since = Instant::now();
+
+        Ok(())
+    }
+
+    // The answer to the sent packet, None while it hasn't fully arrived
+    fn receive(&mut self) -> io::Result<Option<[u8; PACKET_SIZE]>> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(None);
+        };
+
+        while self.recv_len < PACKET_SIZE {
+            match stream.read(&mut self.recv[self.recv_len..]) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.recv_len += len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if !self.stalled && self.waiting_since.elapsed() >= SYNC_TIMEOUT {
+                        eprintln!("Link cable stalled, waiting for the other side");
+                        self.stalled = true;
+                    }
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if self.stalled {
+            println!("Link cable resumed");
+            self.stalled = false;
+        }
+
+        self.awaiting = false;
+        self.recv_len = 0;
+
+        Ok(Some(self.recv))
+    }
+
+    fn disconnect(&mut self, err: io::Error) {
+        eprintln!("Link cable disconnected: {}, reconnecting", err);
+
+        self.stream = None;
+        self.awaiting = false;
+        self.recv_len = 0;
+        self.last_attempt = Instant::now();
+    }
+}
+
+impl SerialConnector for TcpLink {
+    fn send_byte(&mut self, byte: u8) {
+        self.finishing = false;
+        self.finished = false;
+
+        if !self.is_connected() {
+            self.reply_in = Some(0xFF);
+            return;
+        }
+
+        self.transfer_out = Some(byte);
+        self.reply_in = None;
+    }
+
+    fn take(&mut self) -> Option<u8> {
+        self.reply_in.take()
+    }
+
+    fn finish_transfer(&mut self) -> bool {
+        if self.finished || !self.is_connected() {
+            self.finished = false;
+            return true;
+        }
+
+        self.finishing = true;
+        false
+    }
+
+    fn recv_byte(&mut self, byte: u8) -> Option<u8> {
+        self.ready = Some(byte);
+        self.transfer_in.take()
+    }
+
+    fn clock(&mut self, double_speed: bool) {
+        // Set again every cycle by recv_byte while waiting on the external clock
+        let ready = self.ready;
+        self.ready = None;
+        if ready.is_none() {
+            self.pending_in = None;
+        }
+
+        // Not received by a Game Boy which stopped waiting on the external clock
+        self.transfer_in = None;
+
+        self.half_cycles += if double_speed { 1 } else { 2 };
+        if self.half_cycles < SYNC_HALF_CYCLES {
+            return;
+        }
+        self.half_cycles = 0;
+
+        self.ready = ready;
+        self.sync();
+        self.ready = None;
+    }
+}
+
+// Link cable between two Game Boys in the same process, see gameboy::linked. A Game Boy waiting on
+// the external clock answers the transfer right away, but only finishes once the other side has
+// shifted all 8 bits
+pub struct DirectLink {
+    wire: Arc<Mutex<DirectWire>>,
+    side: usize,
+
+    // Transfer clocked by the other side: byte arriving this cycle, byte being shifted in, byte
+    // handed over when the other side is done
+    transfer_in: Option<u8>,
+    pending_in: Option<u8>,
+    received: Option<u8>,
+    // Set by recv_byte while waiting on the external clock
+    waiting: bool,
+}
+
+// Bytes and done flags on their way to each side, indexed by the receiving side
+#[derive(Default)]
+struct DirectWire {
+    transfers: [Option<u8>; 2],
+    replies: [Option<u8>; 2],
+    done: [bool; 2],
+}
+
+impl DirectLink {
+    pub fn pair() -> (DirectLink, DirectLink) {
+        let wire = Arc::new(Mutex::new(DirectWire::default()));
+
+        let link = |side| DirectLink {
+            wire: wire.clone(),
+            side,
+            transfer_in: None,
+            pending_in: None,
+            received: None,
+            waiting: false,
+        };
+
+        (link(0), link(1))
+    }
+
+    fn other_side(&self) -> usize {
+        self.side ^ 1
+    }
+}
+
+impl SerialConnector for DirectLink {
+    fn send_byte(&mut self, byte: u8) {
+        let mut wire = self.wire.lock().unwrap();
+        wire.transfers[self.other_side()] = Some(byte);
+        wire.replies[self.side] = None;
+    }
+
+    fn take(&mut self) -> Option<u8> {
+        self.wire.lock().unwrap().replies[self.side].take()
+    }
+
+    fn finish_transfer(&mut self) -> bool {
+        self.wire.lock().unwrap().done[self.other_side()] = true;
+        true
+    }
+
+    fn recv_byte(&mut self, byte: u8) -> Option<u8> {
+        self.waiting = true;
+
+        if let Some(incoming) = self.transfer_in.take() {
+            self.wire.lock().unwrap().replies[self.other_side()] = Some(byte);
+            self.pending_in = Some(incoming);
+        }
+
+        self.received.take()
+    }
+
+    fn clock(&mut self, _double_speed: bool) {
+        let other_side = self.other_side();
+        let mut wire = self.wire.lock().unwrap();
+
+        // Not received by a Game Boy waiting on the external clock
+        if self.transfer_in.take().is_some() {
+            wire.replies[other_side] = Some(0xFF);
+        }
+        if !self.waiting {
+            self.pending_in = None;
+        }
+        self.waiting = false;
+        self.received = None;
+
+        if wire.done[self.side] {
+            wire.done[self.side] = false;
+            self.received = self.pending_in.take();
+        }
+
+        self.transfer_in = wire.transfers[self.side].take();
+    }
+}
+
+// Other end of the link cable
+pub enum LinkCable {
+    Tcp(TcpLink),
+    Direct(DirectLink),
+}
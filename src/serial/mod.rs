@@ -1,7 +1,28 @@
+pub mod link;
+pub mod peripheral;
 mod printer;
 pub mod serial;
 
-trait SerialConnector {
+// Device on the other end of the serial port. Implement it to connect custom hardware through
+// peripheral::SerialPeripheral::Custom
+pub trait SerialConnector {
+    // Transfer clocked by the Game Boy: byte shifted out of SB
     fn send_byte(&mut self, byte: u8);
+    // Answer to the last sent byte, the transfer waits until there is one
     fn take(&mut self) -> Option<u8>;
+
+    // Transfer clocked by the Game Boy has shifted all bits. Returning false holds the
+    // completion, it's asked again every M-cycle
+    fn finish_transfer(&mut self) -> bool {
+        true
+    }
+
+    // Transfer clocked by the other side while waiting on the external clock. Answers with
+    // byte and returns the byte shifted in, if the other side has sent one
+    fn recv_byte(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+
+    // Called every M-cycle, which takes half as long in double speed mode
+    fn clock(&mut self, _double_speed: bool) {}
 }
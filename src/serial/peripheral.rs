@@ -0,0 +1,82 @@
+use std::{io::Write, path::PathBuf};
+
+use super::{link::LinkCable, printer::Printer, SerialConnector};
+
+// What is plugged into the serial port
+pub enum SerialPeripheral {
+    // Nothing drives the line, transfers read 0xFF
+    None,
+    // Game Boy Printer, pictures are saved to the directory
+    Printer(PathBuf),
+    Link(LinkCable),
+    // Writes every byte sent by the Game Boy, the line is disconnected otherwise
+    Logger(Box<dyn Write + Send>),
+    Custom(Box<dyn SerialConnector + Send>),
+}
+
+impl SerialPeripheral {
+    pub(crate) fn into_connector(self) -> Box<dyn SerialConnector> {
+        match self {
+            SerialPeripheral::None => Box::new(Disconnected::new()),
+            SerialPeripheral::Printer(path) => Box::new(Printer::new(path)),
+            SerialPeripheral::Link(LinkCable::Tcp(link)) => Box::new(link),
+            SerialPeripheral::Link(LinkCable::Direct(link)) => Box::new(link),
+            SerialPeripheral::Logger(output) => Box::new(ByteLogger::new(output)),
+            SerialPeripheral::Custom(connector) => connector,
+        }
+    }
+}
+
+struct Disconnected {
+    reply: Option<u8>,
+}
+
+impl Disconnected {
+    fn new() -> Self {
+        Self { reply: None }
+    }
+}
+
+impl SerialConnector for Disconnected {
+    fn send_byte(&mut self, _byte: u8) {
+        self.reply = Some(0xFF);
+    }
+
+    fn take(&mut self) -> Option<u8> {
+        self.reply.take()
+    }
+}
+
+// Test ROMs print their results over the serial port
+struct ByteLogger {
+    output: Option<Box<dyn Write + Send>>,
+    line: Disconnected,
+}
+
+impl ByteLogger {
+    fn new(output: Box<dyn Write + Send>) -> Self {
+        Self {
+            output: Some(output),
+            line: Disconnected::new(),
+        }
+    }
+}
+
+impl SerialConnector for ByteLogger {
+    fn send_byte(&mut self, byte: u8) {
+        self.line.send_byte(byte);
+
+        let Some(output) = &mut self.output else {
+            return;
+        };
+
+        if let Err(err) = output.write_all(&[byte]).and_then(|_| output.flush()) {
+            eprintln!("Serial logging stopped: {}", err);
+            self.output = None;
+        }
+    }
+
+    fn take(&mut self) -> Option<u8> {
+        self.line.take()
+    }
+}
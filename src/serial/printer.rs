@@ -42,11 +42,11 @@ pub struct Printer {
     ram: Vec<u8>,
     compression: bool,
     output: Option<u8>,
-    image_path: String,
+    image_path: PathBuf,
 }
 
 impl Printer {
-    pub fn new(image_path: &str) -> Self {
+    pub fn new(image_path: PathBuf) -> Self {
         Self {
             ram: vec![0; 0],
             recv_data: vec![0; 0],
@@ -55,7 +55,7 @@ impl Printer {
             recv_data_length: 0,
             compression: false,
             output: None,
-            image_path: image_path.to_string(),
+            image_path,
         }
     }
 }
@@ -124,10 +124,7 @@ impl Printer {
             .expect("current time > UNIX_EPOCH")
             .as_millis();
 
-        let mut path = PathBuf::from(self.image_path.clone());
-        path.push(format!("printer-{timestamp}.bmp"));
-
-        _ = bmp_img.save(path);
+        _ = bmp_img.save(self.image_path.join(format!("printer-{timestamp}.bmp")));
     }
 }
 
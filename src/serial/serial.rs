@@ -1,59 +1,122 @@
-use crate::soc::{interrupt, soc};
+use crate::{
+    soc::{interrupt, soc},
+    CompatibilityMode, GbCtx,
+};
 
-use super::{printer::Printer, SerialConnector};
+use super::{peripheral::SerialPeripheral, SerialConnector};
+
+// The internal clock is taken from the system counter: bits are shifted on the falling edges of
+// bit 8 (8192 Hz) or bit 3 (262144 Hz, CGB only). Both rates double in double speed mode
+const CLOCK_BIT_NORMAL: u16 = 1 << 8;
+const CLOCK_BIT_FAST: u16 = 1 << 3;
 
 pub struct Serial {
     sb: u8,
 
     shift_counter: u8,
+    sent: bool,
     incoming: Option<u8>,
+    last_counter: u16,
 
     reg_enable: bool,
     reg_select: bool,
+    reg_fast_clock: bool,
+
+    connector: Box<dyn SerialConnector>,
 
-    printer: Box<Printer>,
+    ctx: std::rc::Rc<GbCtx>,
 }
 
 impl Serial {
-    pub fn new() -> Self {
+    pub fn new(peripheral: SerialPeripheral, ctx: std::rc::Rc<GbCtx>) -> Self {
         Self {
             sb: 0,
             reg_enable: false,
             reg_select: false,
+            reg_fast_clock: false,
             incoming: None,
+            sent: false,
             shift_counter: 0,
-            printer: Box::new(Printer::new(".")),
+            last_counter: 0,
+            connector: peripheral.into_connector(),
+            ctx,
         }
     }
 
-    pub fn clock(&mut self, ctx: &mut soc::ClockContext) {
-        if !self.reg_enable || !self.reg_select {
+    // Counter is the system counter DIV is the upper byte of
+    pub fn clock(&mut self, counter: u16, double_speed: bool, ctx: &mut soc::ClockContext) {
+        self.connector.clock(double_speed);
+
+        let clock_bit = if self.reg_fast_clock {
+            CLOCK_BIT_FAST
+        } else {
+            CLOCK_BIT_NORMAL
+        };
+        let falling_edge = self.last_counter & clock_bit != 0 && counter & clock_bit == 0;
+        self.last_counter = counter;
+
+        if !self.reg_enable {
+            return;
+        }
+
+        if !self.reg_select {
+            // External clock: driven by the other side, which shifts the whole byte
+            if let Some(incoming) = self.connector.recv_byte(self.sb) {
+                self.sb = incoming;
+                self.finish_transmission(ctx);
+            }
             return;
         }
 
         if self.shift_counter == 8 {
-            self.finish_transmission(ctx);
+            // All bits are shifted, the other side may still need to catch up
+            if self.connector.finish_transfer() {
+                self.finish_transmission(ctx);
+            }
             return;
         }
 
-        if self.shift_counter == 0 {
-            self.printer.as_mut().send_byte(self.sb);
-            self.incoming = self.printer.as_mut().take();
+        if self.incoming.is_none() {
+            // The answer may take a while over a link cable, the transfer waits for it
+            if !self.sent {
+                self.connector.send_byte(self.sb);
+                self.sent = true;
+            }
+            self.incoming = self.connector.take();
         }
 
-        if let Some(incoming) = self.incoming {
-            let curr_bit = 7 - self.shift_counter;
+        let (true, Some(incoming)) = (falling_edge, self.incoming) else {
+            return;
+        };
+
+        let curr_bit = 7 - self.shift_counter;
 
-            self.sb <<= 1;
-            self.sb |= (incoming >> curr_bit) & 0x1;
+        self.sb <<= 1;
+        self.sb |= (incoming >> curr_bit) & 0x1;
 
-            self.shift_counter += 1;
+        self.shift_counter += 1;
+
+        if self.shift_counter == 8 && self.connector.finish_transfer() {
+            self.finish_transmission(ctx);
         }
     }
 
+    // M-cycles per bit with the selected internal clock
+    pub fn bit_cycles(&self) -> u64 {
+        let clock_bit = if self.reg_fast_clock {
+            CLOCK_BIT_FAST
+        } else {
+            CLOCK_BIT_NORMAL
+        };
+
+        // The system counter advances by 4 every M-cycle, a bit takes a full period
+        u64::from(clock_bit) * 2 / 4
+    }
+
     fn finish_transmission(&mut self, ctx: &mut soc::ClockContext) {
         self.reg_enable = false;
         self.shift_counter = 0;
+        self.sent = false;
         self.incoming = None;
         ctx.set_interrupt(interrupt::INTERRUPT_BIT_SERIAL);
     }
@@ -66,13 +129,26 @@ impl Serial {
         self.sb = data;
     }
 
+    // The clock speed bit only exists in CGB mode, unused bits read 1
     pub fn read_sc(&mut self) -> u8 {
-        ((self.reg_enable as u8) << 7) | (self.reg_select as u8) | 0x7E
+        let sc = ((self.reg_enable as u8) << 7) | (self.reg_select as u8);
+
+        if self.ctx.comp_mode == CompatibilityMode::ModeCgb {
+            sc | ((self.reg_fast_clock as u8) << 1) | 0x7C
+        } else {
+            sc | 0x7E
+        }
     }
 
     pub fn write_sc(&mut self, data: u8) {
-        // @todo CGB: Clock speed bit
         self.reg_enable = data & 0x80 != 0;
         self.reg_select = data & 0x1 != 0;
+        self.reg_fast_clock = data & 0x2 != 0 && self.ctx.comp_mode == CompatibilityMode::ModeCgb;
+
+        if !self.reg_enable {
+            self.shift_counter = 0;
+            self.sent = false;
+            self.incoming = None;
+        }
     }
 }
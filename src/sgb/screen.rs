@@ -0,0 +1,194 @@
+use crate::ppu::ppu::FrameBuffer;
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+// Position of the Game Boy screen inside the border
+const GB_SCREEN_X: usize = 48;
+const GB_SCREEN_Y: usize = 40;
+
+// Border pixels are stored with this bit set, transparent pixels are 0
+const BORDER_OPAQUE_BIT: u16 = 0x8000;
+
+pub type SgbFrameBuffer = [[u16; SGB_SCREEN_WIDTH]; SGB_SCREEN_HEIGHT];
+pub type SgbFrameSender = std::sync::mpsc::SyncSender<SgbFrameBuffer>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SgbMask {
+    Cancel = 0,
+    Freeze = 1,
+    Black = 2,
+    Color0 = 3,
+}
+
+// Part of the Super Game Boy state which is needed to output colors, owned by the PPU
+pub struct SgbScreen {
+    // Palettes 0-3, color 0 is shared between all palettes
+    palettes: [[u16; 4]; 4],
+    // Palette of each 8x8 area on the screen (20x18)
+    attributes: [u8; 20 * 18],
+    mask: SgbMask,
+
+    // SNES 4bpp tiles, border map and border palettes 4-7 (PCT_TRN)
+    border_tiles: Box<[u8; 0x2000]>,
+    border_map: Box<[u8; 0x800]>,
+    border_palettes: [[u16; 16]; 4],
+    border: Box<SgbFrameBuffer>,
+    border_dirty: bool,
+
+    // Border with the Game Boy screen on top, reused every frame
+    frame: Box<SgbFrameBuffer>,
+}
+
+impl Default for SgbScreen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SgbScreen {
+    pub fn new() -> Self {
+        Self {
+            palettes: [[0x67BF, 0x265B, 0x10B5, 0x2866]; 4],
+            attributes: [0; 20 * 18],
+            mask: SgbMask::Cancel,
+            border_tiles: vec![0; 0x2000].into_boxed_slice().try_into().unwrap(),
+            border_map: vec![0; 0x800].into_boxed_slice().try_into().unwrap(),
+            border_palettes: [[0; 16]; 4],
+            border: vec![[0; SGB_SCREEN_WIDTH]; SGB_SCREEN_HEIGHT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+            border_dirty: false,
+            frame: vec![[0; SGB_SCREEN_WIDTH]; SGB_SCREEN_HEIGHT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+        }
+    }
+
+    pub fn set_palette_color(&mut self, palette: usize, color_index: usize, color: u16) {
+        if color_index == 0 {
+            for palette in self.palettes.iter_mut() {
+                palette[0] = color & 0x7FFF;
+            }
+        } else {
+            self.palettes[palette][color_index] = color & 0x7FFF;
+        }
+    }
+
+    pub fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < 20 && y < 18 {
+            self.attributes[y * 20 + x] = palette & 0x3;
+        }
+    }
+
+    pub fn set_mask(&mut self, mask: SgbMask) {
+        self.mask = mask;
+    }
+
+    pub fn load_border_tiles(&mut self, upper: bool, data: &[u8; 0x1000]) {
+        let offset = if upper { 0x1000 } else { 0 };
+        self.border_tiles[offset..offset + 0x1000].copy_from_slice(data);
+        self.border_dirty = true;
+    }
+
+    pub fn load_border(&mut self, data: &[u8; 0x1000]) {
+        self.border_map.copy_from_slice(&data[..0x800]);
+
+        for (palette_index, palette) in self.border_palettes.iter_mut().enumerate() {
+            for (color_index, color) in palette.iter_mut().enumerate() {
+                let offset = 0x800 + (palette_index * 16 + color_index) * 2;
+                *color = u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7FFF;
+            }
+        }
+
+        self.border_dirty = true;
+    }
+
+    pub fn output_scanline(&self, ly: usize, dmg_scanline: &[(u8, u8); 160], out: &mut [u16; 160]) {
+        match self.mask {
+            SgbMask::Cancel => {
+                let attributes = &self.attributes[(ly / 8) * 20..(ly / 8) * 20 + 20];
+
+                for (x, (_, dmg_color)) in dmg_scanline.iter().enumerate() {
+                    let palette = attributes[x / 8] as usize;
+                    out[x] = self.palettes[palette][usize::from(*dmg_color & 0x3)];
+                }
+            }
+            // Keep showing the last frame
+            SgbMask::Freeze => {}
+            SgbMask::Black => out.fill(0),
+            SgbMask::Color0 => out.fill(self.palettes[0][0]),
+        }
+    }
+
+    pub fn compose_frame(&mut self, rt: &FrameBuffer) -> &SgbFrameBuffer {
+        if self.border_dirty {
+            self.render_border();
+            self.border_dirty = false;
+        }
+
+        let backdrop = self.palettes[0][0];
+
+        for (y, (line, border_line)) in self.frame.iter_mut().zip(self.border.iter()).enumerate() {
+            for (x, (pixel, border_pixel)) in line.iter_mut().zip(border_line.iter()).enumerate() {
+                *pixel = if *border_pixel & BORDER_OPAQUE_BIT != 0 {
+                    *border_pixel & !BORDER_OPAQUE_BIT
+                } else if (GB_SCREEN_X..GB_SCREEN_X + 160).contains(&x)
+                    && (GB_SCREEN_Y..GB_SCREEN_Y + 144).contains(&y)
+                {
+                    rt[y - GB_SCREEN_Y][x - GB_SCREEN_X]
+                } else {
+                    backdrop
+                };
+            }
+        }
+
+        &self.frame
+    }
+
+    fn render_border(&mut self) {
+        for map_y in 0..SGB_SCREEN_HEIGHT / 8 {
+            for map_x in 0..SGB_SCREEN_WIDTH / 8 {
+                let map_index = (map_y * 32 + map_x) * 2;
+                let entry = u16::from_le_bytes([
+                    self.border_map[map_index],
+                    self.border_map[map_index + 1],
+                ]);
+
+                let tile = usize::from(entry & 0xFF);
+                let palette = usize::from((entry >> 10) & 0x7).saturating_sub(4);
+                let x_flip = entry & (1 << 14) != 0;
+                let y_flip = entry & (1 << 15) != 0;
+
+                for tile_y in 0..8 {
+                    let row = if y_flip { 7 - tile_y } else { tile_y };
+                    let row_addr = tile * 32 + row * 2;
+
+                    let planes = [
+                        self.border_tiles[row_addr],
+                        self.border_tiles[row_addr + 1],
+                        self.border_tiles[row_addr + 16],
+                        self.border_tiles[row_addr + 17],
+                    ];
+
+                    for tile_x in 0..8 {
+                        let bit = if x_flip { tile_x } else { 7 - tile_x };
+                        let color_index =
+                            planes.iter().enumerate().fold(0, |acc, (plane, bits)| {
+                                acc | (((bits >> bit) & 0x1) << plane)
+                            });
+
+                        self.border[map_y * 8 + tile_y][map_x * 8 + tile_x] = if color_index == 0 {
+                            0
+                        } else {
+                            self.border_palettes[palette][usize::from(color_index)]
+                                | BORDER_OPAQUE_BIT
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use crate::ppu::ppu::PPU;
+
+use super::screen::{SgbMask, SgbScreen};
+
+// https://gbdev.io/pandocs/SGB_Command_Summary.html
+const SGB_PAL01: u8 = 0x00;
+const SGB_PAL23: u8 = 0x01;
+const SGB_PAL03: u8 = 0x02;
+const SGB_PAL12: u8 = 0x03;
+const SGB_ATTR_BLK: u8 = 0x04;
+const SGB_ATTR_LIN: u8 = 0x05;
+const SGB_ATTR_DIV: u8 = 0x06;
+const SGB_ATTR_CHR: u8 = 0x07;
+const SGB_PAL_SET: u8 = 0x0A;
+const SGB_PAL_TRN: u8 = 0x0B;
+const SGB_MLT_REQ: u8 = 0x11;
+const SGB_CHR_TRN: u8 = 0x13;
+const SGB_PCT_TRN: u8 = 0x14;
+const SGB_ATTR_TRN: u8 = 0x15;
+const SGB_ATTR_SET: u8 = 0x16;
+const SGB_MASK_EN: u8 = 0x17;
+
+const PACKET_BITS: u8 = 128;
+
+const ATTR_FILE_SIZE: usize = 90;
+const ATTR_FILE_COUNT: usize = 45;
+
+// Data copied from the Game Boy screen (VRAM) on the next frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VramTransfer {
+    Palettes,
+    BorderTiles(bool),
+    Border,
+    AttributeFiles,
+}
+
+pub struct Sgb {
+    // Packet transfer through P14/P15
+    p1_lines: u8,
+    receiving: bool,
+    bit_ready: bool,
+    packet_bits: u8,
+    packet: [u8; 16],
+    command: Vec<u8>,
+    packets_left: u8,
+
+    // Multiplayer (MLT_REQ)
+    players: u8,
+    player: u8,
+
+    system_palettes: Box<[[u16; 4]; 512]>,
+    attribute_files: Box<[[u8; ATTR_FILE_SIZE]; ATTR_FILE_COUNT]>,
+
+    vram_transfer: Option<VramTransfer>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Self {
+            p1_lines: 0x30,
+            receiving: false,
+            bit_ready: false,
+            packet_bits: 0,
+            packet: [0; 16],
+            command: Vec::with_capacity(16 * 7),
+            packets_left: 0,
+            players: 1,
+            player: 0,
+            system_palettes: vec![[0; 4]; 512].into_boxed_slice().try_into().unwrap(),
+            attribute_files: vec![[0; ATTR_FILE_SIZE]; ATTR_FILE_COUNT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+            vram_transfer: None,
+        }
+    }
+
+    // Current player when multiplayer mode is enabled, 0 = player 1
+    pub fn get_player(&self) -> u8 {
+        self.player
+    }
+
+    // Lower nibble of P1 when neither buttons nor d-pad are selected
+    pub fn read_joypad_id(&self) -> u8 {
+        0xF - self.player
+    }
+
+    pub fn write_p1(&mut self, data: u8, ppu: &mut PPU) {
+        let lines = data & 0x30;
+        let prev_lines = self.p1_lines;
+        self.p1_lines = lines;
+
+        match lines {
+            0x00 => {
+                // Reset pulse, start of a new packet
+                self.receiving = true;
+                self.bit_ready = false;
+                self.packet_bits = 0;
+                self.packet = [0; 16];
+            }
+            0x30 => {
+                self.bit_ready = self.receiving;
+
+                // Rising edge of P15 selects the next joypad
+                if !self.receiving && prev_lines == 0x10 && self.players > 1 {
+                    self.player = (self.player + 1) % self.players;
+                }
+            }
+            _ => {
+                if !self.receiving || !self.bit_ready {
+                    return;
+                }
+                self.bit_ready = false;
+
+                let bit = lines == 0x10;
+
+                if self.packet_bits == PACKET_BITS {
+                    // Stop bit
+                    self.receiving = false;
+                    if !bit {
+                        self.receive_packet(ppu);
+                    }
+                    return;
+                }
+
+                if bit {
+                    self.packet[usize::from(self.packet_bits / 8)] |= 1 << (self.packet_bits % 8);
+                }
+                self.packet_bits += 1;
+            }
+        }
+    }
+
+    pub fn vsync(&mut self, ppu: &mut PPU) {
+        let transfer = match self.vram_transfer.take() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        let data = ppu.read_sgb_transfer();
+
+        match transfer {
+            VramTransfer::Palettes => {
+                for (index, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (color_index, color) in palette.iter_mut().enumerate() {
+                        let offset = index * 8 + color_index * 2;
+                        *color = u16::from_le_bytes([data[offset], data[offset + 1]]);
+                    }
+                }
+            }
+            VramTransfer::BorderTiles(upper) => {
+                if let Some(screen) = ppu.get_sgb_screen() {
+                    screen.load_border_tiles(upper, &data);
+                }
+            }
+            VramTransfer::Border => {
+                if let Some(screen) = ppu.get_sgb_screen() {
+                    screen.load_border(&data);
+                }
+            }
+            VramTransfer::AttributeFiles => {
+                for (index, attribute_file) in self.attribute_files.iter_mut().enumerate() {
+                    let offset = index * ATTR_FILE_SIZE;
+                    attribute_file.copy_from_slice(&data[offset..offset + ATTR_FILE_SIZE]);
+                }
+            }
+        }
+    }
+
+    fn receive_packet(&mut self, ppu: &mut PPU) {
+        if self.packets_left == 0 {
+            let packet_count = self.packet[0] & 0x7;
+            if packet_count == 0 {
+                return;
+            }
+            self.command.clear();
+            self.packets_left = packet_count;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        self.packets_left -= 1;
+
+        if self.packets_left == 0 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command, ppu);
+            self.command = command;
+        }
+    }
+
+    fn execute(&mut self, command: &[u8], ppu: &mut PPU) {
+        let screen = match ppu.get_sgb_screen() {
+            Some(screen) => screen,
+            None => return,
+        };
+
+        match command[0] >> 3 {
+            SGB_PAL01 => Sgb::set_palettes(screen, command, 0, 1),
+            SGB_PAL23 => Sgb::set_palettes(screen, command, 2, 3),
+            SGB_PAL03 => Sgb::set_palettes(screen, command, 0, 3),
+            SGB_PAL12 => Sgb::set_palettes(screen, command, 1, 2),
+            SGB_ATTR_BLK => Sgb::attr_blk(screen, command),
+            SGB_ATTR_LIN => Sgb::attr_lin(screen, command),
+            SGB_ATTR_DIV => Sgb::attr_div(screen, command),
+            SGB_ATTR_CHR => Sgb::attr_chr(screen, command),
+            SGB_PAL_SET => {
+                for palette in 0..4 {
+                    let system_palette =
+                        u16::from_le_bytes([command[1 + palette * 2], command[2 + palette * 2]]);
+                    let colors = self.system_palettes[usize::from(system_palette & 0x1FF)];
+
+                    for (color_index, color) in colors.iter().enumerate() {
+                        // Color 0 is shared, it's taken from the first palette
+                        if color_index != 0 || palette == 0 {
+                            screen.set_palette_color(palette, color_index, *color);
+                        }
+                    }
+                }
+
+                if command[9] & 0x80 != 0 {
+                    self.apply_attribute_file(screen, command[9] & 0x3F);
+                }
+                if command[9] & 0x40 != 0 {
+                    screen.set_mask(SgbMask::Cancel);
+                }
+            }
+            SGB_ATTR_SET => {
+                self.apply_attribute_file(screen, command[1] & 0x3F);
+                if command[1] & 0x40 != 0 {
+                    screen.set_mask(SgbMask::Cancel);
+                }
+            }
+            SGB_MASK_EN => {
+                let mask = match command[1] & 0x3 {
+                    0 => SgbMask::Cancel,
+                    1 => SgbMask::Freeze,
+                    2 => SgbMask::Black,
+                    _ => SgbMask::Color0,
+                };
+                screen.set_mask(mask);
+            }
+            SGB_MLT_REQ => {
+                self.players = match command[1] & 0x3 {
+                    0 => 1,
+                    1 => 2,
+                    // 2 is invalid, treated as 4 players
+                    _ => 4,
+                };
+                self.player = 0;
+            }
+            SGB_PAL_TRN => self.vram_transfer = Some(VramTransfer::Palettes),
+            SGB_CHR_TRN => {
+                self.vram_transfer = Some(VramTransfer::BorderTiles(command[1] & 0x1 != 0))
+            }
+            SGB_PCT_TRN => self.vram_transfer = Some(VramTransfer::Border),
+            SGB_ATTR_TRN => self.vram_transfer = Some(VramTransfer::AttributeFiles),
+            _ => {
+                // @todo - Sound, SNES program and icon commands are not supported
+            }
+        }
+    }
+
+    fn set_palettes(screen: &mut SgbScreen, command: &[u8], first: usize, second: usize) {
+        let color =
+            |index: usize| u16::from_le_bytes([command[1 + index * 2], command[2 + index * 2]]);
+
+        screen.set_palette_color(0, 0, color(0));
+
+        for color_index in 1..4 {
+            screen.set_palette_color(first, color_index, color(color_index));
+            screen.set_palette_color(second, color_index, color(color_index + 3));
+        }
+    }
+
+    fn attr_blk(screen: &mut SgbScreen, command: &[u8]) {
+        let data_sets = usize::from(command[1] & 0x1F);
+
+        for data_set in command[2..].chunks_exact(6).take(data_sets) {
+            let control = data_set[0] & 0x7;
+            let palette_inside = data_set[1] & 0x3;
+            let palette_border = (data_set[1] >> 2) & 0x3;
+            let palette_outside = (data_set[1] >> 4) & 0x3;
+
+            // When only inside or outside is changed, the border is changed as well
+            let (change_border, palette_border) = match control {
+                0x1 => (true, palette_inside),
+                0x4 => (true, palette_outside),
+                _ => (control & 0x2 != 0, palette_border),
+            };
+
+            let x1 = usize::from(data_set[2] & 0x1F);
+            let y1 = usize::from(data_set[3] & 0x1F);
+            let x2 = usize::from(data_set[4] & 0x1F);
+            let y2 = usize::from(data_set[5] & 0x1F);
+
+            for y in 0..18 {
+                for x in 0..20 {
+                    let inside_x = x > x1 && x < x2;
+                    let inside_y = y > y1 && y < y2;
+                    let on_x = x >= x1 && x <= x2;
+                    let on_y = y >= y1 && y <= y2;
+
+                    if inside_x && inside_y {
+                        if control & 0x1 != 0 {
+                            screen.set_attribute(x, y, palette_inside);
+                        }
+                    } else if on_x && on_y {
+                        if change_border {
+                            screen.set_attribute(x, y, palette_border);
+                        }
+                    } else if control & 0x4 != 0 {
+                        screen.set_attribute(x, y, palette_outside);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(screen: &mut SgbScreen, command: &[u8]) {
+        let data_sets = usize::from(command[1]);
+
+        for data in command[2..].iter().take(data_sets) {
+            let line = usize::from(data & 0x1F);
+            let palette = (data >> 5) & 0x3;
+            let horizontal = data & 0x80 != 0;
+
+            if horizontal {
+                for x in 0..20 {
+                    screen.set_attribute(x, line, palette);
+                }
+            } else {
+                for y in 0..18 {
+                    screen.set_attribute(line, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(screen: &mut SgbScreen, command: &[u8]) {
+        let palette_after = command[1] & 0x3;
+        let palette_before = (command[1] >> 2) & 0x3;
+        let palette_line = (command[1] >> 4) & 0x3;
+        let horizontal = command[1] & 0x40 != 0;
+        let division = usize::from(command[2] & 0x1F);
+
+        for y in 0..18 {
+            for x in 0..20 {
+                let position = if horizontal { y } else { x };
+
+                let palette = match position.cmp(&division) {
+                    std::cmp::Ordering::Less => palette_before,
+                    std::cmp::Ordering::Equal => palette_line,
+                    std::cmp::Ordering::Greater => palette_after,
+                };
+
+                screen.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(screen: &mut SgbScreen, command: &[u8]) {
+        let mut x = usize::from(command[1] % 20);
+        let mut y = usize::from(command[2] % 18);
+        let count = usize::from(u16::from_le_bytes([command[3], command[4]])).min(20 * 18);
+        let vertical = command[5] & 0x1 != 0;
+
+        for index in 0..count {
+            let data = match command.get(6 + index / 4) {
+                Some(data) => data,
+                None => break,
+            };
+            let palette = (data >> (6 - (index % 4) * 2)) & 0x3;
+
+            screen.set_attribute(x, y, palette);
+
+            if vertical {
+                y += 1;
+                if y == 18 {
+                    y = 0;
+                    x = (x + 1) % 20;
+                }
+            } else {
+                x += 1;
+                if x == 20 {
+                    x = 0;
+                    y = (y + 1) % 18;
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&self, screen: &mut SgbScreen, attribute_file: u8) {
+        let attributes = match self.attribute_files.get(usize::from(attribute_file)) {
+            Some(attributes) => attributes,
+            None => return,
+        };
+
+        for (index, data) in attributes.iter().enumerate() {
+            for tile in 0..4 {
+                let position = index * 4 + tile;
+                let palette = (data >> (6 - tile * 2)) & 0x3;
+                screen.set_attribute(position % 20, position / 20, palette);
+            }
+        }
+    }
+}
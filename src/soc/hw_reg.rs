@@ -43,6 +43,7 @@ pub const HWR_WX: u16 = 0xFF4B;
 
 pub const HWR_KEY1: u16 = 0xFF4D;
 pub const HWR_VBK: u16 = 0xFF4F;
+pub const HWR_BANK: u16 = 0xFF50;
 
 pub const HWR_HDMA1: u16 = 0xFF51;
 pub const HWR_HDMA2: u16 = 0xFF52;
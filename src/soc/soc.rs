@@ -7,11 +7,17 @@ use crate::{
         mbc::{MbcRomOnly, MBC},
         mbc1, mbc2, mbc3, mbc5,
     },
-    ppu::ppu::{self, FrameBuffer, PPU},
+    ppu::{
+        palettes,
+        ppu::{self, FrameBuffer, OamBugAccess, PPU},
+        viewer::PpuViewerSender,
+    },
     serial::serial,
+    sgb::sgb::Sgb,
     timer::timer::Timer,
     util::util,
-    CompatibilityMode, GbButton, GbCtx, InputReceiver,
+    CommandReceiver, CompatibilityMode, EmulatorCommand, EmulatorConfig, GbButton, GbCtx,
+    HardwareModel, InputReceiver,
 };
 
 use super::{hw_reg::*, interrupt::INTERRUPT_BIT_JOYPAD};
@@ -22,6 +28,29 @@ pub enum SocEventBits {
     SocEventsVSyncAndExit = 1 << 1,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MemoryBus {
+    External,
+    Video,
+    // CGB only, on DMG WRAM is connected to the external bus
+    Wram,
+}
+
+// M-cycles per frame in normal speed
+const CYCLES_PER_FRAME: u32 = 154 * 114;
+
+// Speed switch pause in M-cycles, DIV doesn't tick meanwhile
+const CYCLES_SPEED_SWITCH: u16 = 2050;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StopMode {
+    None,
+    // Very low power mode, only the joypad can wake up the CPU
+    Stopped,
+    // CPU paused while switching speed, M-cycles left
+    SpeedSwitch(u16),
+}
+
 struct DmaTransfer {
     src: u16,
     count: u8,
@@ -29,11 +58,11 @@ struct DmaTransfer {
 }
 
 struct Hdma {
-    src: u16,
-    dst: u16,
-    initial: u16,
-    count: u16,
+    // Bytes left to transfer, the addresses are kept in hdma_src/hdma_dst
+    remaining: u16,
     hblank_dma: bool,
+    // HBlank DMA transfers one block of 0x10 bytes per HBlank
+    block_pending: bool,
 }
 
 pub struct ClockContext<'a> {
@@ -76,6 +105,7 @@ pub struct SOC {
     timer: Timer,
     ppu: ppu::PPU,
     serial: serial::Serial,
+    sgb: Option<Box<Sgb>>,
 
     // C000	CFFF	4 KiB Work RAM (WRAM)
     // D000	DFFF	4 KiB Work RAM (WRAM) CGB: bank 1–7 (svbk)
@@ -90,14 +120,22 @@ pub struct SOC {
     dma: u8,
 
     mbc: Box<dyn MBC>,
+    // Mapped over the cartridge until FF50 is written
+    boot_rom: Option<Vec<u8>>,
 
     dma_request: Option<u8>,
     active_dma: Option<DmaTransfer>,
+    // Last byte read by OAM DMA, seen by the CPU on bus conflicts
+    dma_bus_value: u8,
 
     buttons: [bool; 8],
 
     event_bits: u8,
     input_recv: Option<InputReceiver>,
+    command_recv: Option<CommandReceiver>,
+
+    viewer_chan: Option<PpuViewerSender>,
+    viewer_enabled: bool,
 
     enable_saving: bool,
     last_saved_at: std::time::Instant,
@@ -110,6 +148,8 @@ pub struct SOC {
     hdma_src: u16,
     hdma_dst: u16,
     hdma: Option<Box<Hdma>>,
+    // HDMA5 value while no transfer is active
+    hdma5: u8,
 
     // Undocumented registers
     hwr_ff72: u8,
@@ -118,6 +158,11 @@ pub struct SOC {
     hwr_ff75: u8,
 
     cpu_halted: bool,
+    stop_mode: StopMode,
+    stop_cycles: u32,
+
+    // Address on the 16-bit inc/dec unit during the current M-cycle, DMG OAM bug only
+    oam_bug_inc_dec: Option<u16>,
 
     cpu_speed: bool,
     cpu_speed_armed: bool,
@@ -126,27 +171,35 @@ pub struct SOC {
 impl SOC {
     pub fn new(
         cartridge: &Cartridge,
-        input_recv: Option<InputReceiver>,
-        sound_chan: Option<apu::ApuSoundSender>,
-        frame_chan: Option<ppu::PpuFrameSender>,
-        enable_saving: bool,
-        sync_audio: bool,
-        sync_video: bool,
-        run_for_cycles: Option<u64>,
+        config: EmulatorConfig,
+        boot_rom: Option<Vec<u8>>,
         ctx: std::rc::Rc<GbCtx>,
     ) -> SOC {
+        // DIV counts from power-on when the boot ROM runs. Without it the SGB models fall back
+        // to the DMG hand-over, their own isn't documented
+        let div = match (&boot_rom, ctx.model.div_seed(ctx.comp_mode)) {
+            (Some(_), _) => 0,
+            (None, Some(div)) => div,
+            (None, None) => HardwareModel::Dmg.div_seed(ctx.comp_mode).unwrap_or(0),
+        };
+
         let mut soc = Self {
             ctx: ctx.clone(),
-            input_recv,
-            enable_saving,
-            run_for_cycles,
+            input_recv: config.input_recv,
+            command_recv: config.command_recv,
+            viewer_chan: config.viewer_chan,
+            viewer_enabled: false,
+            enable_saving: config.enable_saving,
+            run_for_cycles: config.max_cycles,
             cycles: 0,
             wram: vec![0; 0x12000],
             hram: vec![0; 0x7F],
             svbk: 0,
             mbc: Box::new(MbcRomOnly::new()),
+            boot_rom,
             active_dma: None,
             dma_request: None,
+            dma_bus_value: 0xFF,
 
             buttons: [false; GbButton::GbButtonMax as usize],
             event_bits: 0,
@@ -157,16 +210,33 @@ impl SOC {
             ie: 0x0,
             dma: 0xFF,
 
-            apu: apu::APU::new(sound_chan, sync_audio, ctx.clone()),
-            ppu: ppu::PPU::new(frame_chan, sync_video, ctx.clone()),
-            timer: Timer::new(),
-            serial: serial::Serial::new(),
+            apu: apu::APU::new(
+                config.sound_chan,
+                config.sync_audio,
+                config.audio,
+                ctx.clone(),
+            ),
+            ppu: ppu::PPU::new(
+                config.frame_chan,
+                config.sgb_frame_chan,
+                config.sync_video,
+                config.frame_blending,
+                ctx.clone(),
+            ),
+            timer: Timer::new(div),
+            serial: serial::Serial::new(config.serial, ctx.clone()),
+            sgb: if ctx.comp_mode == CompatibilityMode::ModeSgb {
+                Some(Box::new(Sgb::new()))
+            } else {
+                None
+            },
 
             last_saved_at: time::Instant::now(),
 
             hdma_dst: 0,
             hdma_src: 0,
             hdma: None,
+            hdma5: 0xFF,
 
             hwr_ff72: 0,
             hwr_ff73: 0,
@@ -175,19 +245,53 @@ impl SOC {
             hwr_ff75: 0,
 
             cpu_halted: false,
+            stop_mode: StopMode::None,
+            stop_cycles: 0,
+            oam_bug_inc_dec: None,
             cpu_speed: false,
             cpu_speed_armed: false,
         };
 
-        if soc.ctx.comp_mode != CompatibilityMode::ModeDmg {
+        if soc.ctx.comp_mode.is_cgb_hardware() {
             soc.p1_select_buttons = true;
             soc.p1_select_dpad = true;
         }
 
+        if soc.boot_rom.is_some() {
+            soc.power_on();
+        } else {
+            soc.init_hwio();
+        }
+
+        if let Some(compat_palette) =
+            palettes::compat_palette(&cartridge.header, soc.ctx.comp_mode, config.dmg_palette)
+        {
+            soc.ppu.load_compat_palette(&compat_palette);
+        }
+
         soc.load(&cartridge);
         soc
     }
 
+    // The boot ROM enables the LCD and the APU itself
+    fn power_on(&mut self) {
+        self.ppu.write_lcdc(0x00);
+        self.apu.write_nr52(0x00);
+    }
+
+    // IO registers which the boot ROM of each model leaves in a different state
+    // https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+    fn init_hwio(&mut self) {
+        match self.ctx.model {
+            // Hands over during VBlank, LY = 0x91 and STAT = 0x81
+            HardwareModel::Dmg0 => self.ppu.boot_in_vblank(0x91),
+            HardwareModel::Dmg | HardwareModel::Mgb => {}
+            // No startup sound, NR52 = 0xF0
+            HardwareModel::Sgb | HardwareModel::Sgb2 => self.apu.skip_boot_sound(),
+            HardwareModel::Cgb | HardwareModel::CgbE | HardwareModel::Agb => self.dma = 0x00,
+        }
+    }
+
     pub fn load(&mut self, cartridge: &Cartridge) {
         self.mbc = match cartridge.header.cart_type {
             0x1..=0x3 => Box::new(mbc1::MBC1::new()),
@@ -212,12 +316,21 @@ impl SOC {
     #[rustfmt::skip]
     pub fn clock_read(&mut self, address: u16) -> u8 {
         let active_dma = self.active_dma.is_some();
+        let dma_conflict = self.dma_bus_conflict(address);
+        self.oam_bug(address, OamBugAccess::Read);
         self.clock();
 
+        if dma_conflict {
+            return self.dma_bus_value;
+        }
+
         // https://gbdev.io/pandocs/Memory_Map.html
         return match address {
             0x0000..=0x7FFF => {
-                self.mbc.read(address)
+                match self.boot_rom_byte(address) {
+                    Some(data) => data,
+                    None => self.mbc.read(address),
+                }
             }
             0x8000..=0x9FFF => {
                 self.ppu.read_vram(address)
@@ -238,7 +351,7 @@ impl SOC {
             }
             0xFE00..=0xFE9F => {
                 if active_dma {
-                    // @todo OAM DMA Bus conflicts
+                    // OAM is owned by OAM DMA
                     return 0xFF;
                 }
                 self.ppu.read_oam(address)
@@ -250,11 +363,18 @@ impl SOC {
             0xFF00..=0xFF7F => {
                 match address {
                     HWR_P1 => {
-                        let button_bits = util::calc_button_bits(
-                            &self.buttons,
-                            !self.p1_select_buttons,
-                            !self.p1_select_dpad,
-                        );
+                        let button_bits = match &self.sgb {
+                            Some(sgb) if self.p1_select_buttons && self.p1_select_dpad => {
+                                sgb.read_joypad_id()
+                            }
+                            // Only player 1 has buttons connected
+                            Some(sgb) if sgb.get_player() != 0 => 0xF,
+                            _ => util::calc_button_bits(
+                                &self.buttons,
+                                !self.p1_select_buttons,
+                                !self.p1_select_dpad,
+                            ),
+                        };
                         return button_bits
                             | ((self.p1_select_buttons as u8) << 5)
                             | ((self.p1_select_dpad as u8) << 4)
@@ -311,12 +431,10 @@ impl SOC {
                     HWR_VBK             => self.ppu.read_vbk(),
                     HWR_HDMA5           => {
                         if let Some(active_hdma) = &self.hdma {
-                            if active_hdma.hblank_dma {
-                                let blocks_left = ((active_hdma.initial - active_hdma.count) / 0x10) - 1;
-                                return (blocks_left as u8) & 0x7F;
-                            }
+                            let blocks_left = (active_hdma.remaining / 0x10) - 1;
+                            return (blocks_left as u8) & 0x7F;
                         }
-                        return 0xFF;
+                        return self.hdma5;
                     }
                     HWR_BCPS            => self.ppu.read_bcps(),
                     HWR_BCPD            => self.ppu.read_bcpd(),
@@ -324,12 +442,12 @@ impl SOC {
                     HWR_OCPD            => self.ppu.read_ocpd(),
                     HWR_OPRI            => self.ppu.read_opri(),
                     HWR_SVBK            => { if self.ctx.cgb { self.svbk } else { 0xFF } }
-                    HWR_FF72            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { self.hwr_ff72 } else { 0xFF },
-                    HWR_FF73            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { self.hwr_ff73 } else { 0xFF },
-                    HWR_FF74            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { self.hwr_ff74 } else { 0xFF },
-                    HWR_FF75            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { self.hwr_ff75 | 0x8F } else { 0xFF },
-                    HWR_FF76            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { 0x00 } else { 0xFF },
-                    HWR_FF77            => if self.ctx.comp_mode != CompatibilityMode::ModeDmg { 0x00 } else { 0xFF },
+                    HWR_FF72            => if self.ctx.comp_mode.is_cgb_hardware() { self.hwr_ff72 } else { 0xFF },
+                    HWR_FF73            => if self.ctx.comp_mode.is_cgb_hardware() { self.hwr_ff73 } else { 0xFF },
+                    HWR_FF74            => if self.ctx.comp_mode.is_cgb_hardware() { self.hwr_ff74 } else { 0xFF },
+                    HWR_FF75            => if self.ctx.comp_mode.is_cgb_hardware() { self.hwr_ff75 | 0x8F } else { 0xFF },
+                    HWR_FF76            => if self.ctx.comp_mode.is_cgb_hardware() { self.apu.read_pcm12() } else { 0xFF },
+                    HWR_FF77            => if self.ctx.comp_mode.is_cgb_hardware() { self.apu.read_pcm34() } else { 0xFF },
                     _                   => 0xFF,
                 }
             }
@@ -344,6 +462,14 @@ impl SOC {
 
     #[rustfmt::skip]
     pub fn clock_write(&mut self, address: u16, data: u8) {
+        self.oam_bug(address, OamBugAccess::Write);
+
+        if self.dma_bus_conflict(address) {
+            // The bus is driven by OAM DMA, the write is lost
+            self.clock();
+            return;
+        }
+
         match address {
             0x0000..=0x7FFF => {
                 self.clock();
@@ -375,7 +501,7 @@ impl SOC {
                 let active_dma = self.active_dma.is_some();
                 self.clock();
                 if active_dma {
-                    // @todo OAM DMA Bus conflicts
+                    // OAM is owned by OAM DMA
                     return;
                 }
                 self.ppu.write_oam(address, data);
@@ -391,6 +517,10 @@ impl SOC {
                         self.clock();
                         self.p1_select_buttons = data & 0x20 != 0;
                         self.p1_select_dpad = data & 0x10 != 0;
+
+                        if let Some(sgb) = &mut self.sgb {
+                            sgb.write_p1(data, &mut self.ppu);
+                        }
                     }
                     HWR_SB => { self.clock(); self.serial.write_sb(data) },
                     HWR_SC => { self.clock(); self.serial.write_sc(data) },
@@ -448,6 +578,7 @@ impl SOC {
                         self.cpu_speed_armed = data & 0x1 != 0;
                     }
                     HWR_VBK                 => { self.clock(); self.ppu.write_vbk(data) },
+                    HWR_BANK                => { self.clock(); if data & 0x1 != 0 { self.boot_rom = None } },
                     HWR_HDMA1               => { self.clock(); util::set_high(&mut self.hdma_src, data); }
                     HWR_HDMA2               => { self.clock(); util::set_low(&mut self.hdma_src, data); }
                     HWR_HDMA3               => { self.clock(); util::set_high(&mut self.hdma_dst, data); }
@@ -459,15 +590,29 @@ impl SOC {
                             return;
                         }
 
+                        let hblank_dma = data & 0x80 != 0;
                         let length_bits = data & 0x7F;
-                        let count = u16::from(length_bits + 1) * 0x10;
-                        self.hdma = Some(Box::new(Hdma {
-                            dst: self.hdma_dst & 0x1FF0,
-                            src: self.hdma_src & 0xFFF0,
-                            initial: count,
-                            count: 0,
-                            hblank_dma: data & 0x80 != 0
-                        }));
+
+                        match &self.hdma {
+                            // Writing bit 7 = 0 during HBlank DMA cancels the transfer
+                            Some(active_hdma) if active_hdma.hblank_dma && !hblank_dma => {
+                                let blocks_left = (active_hdma.remaining / 0x10) - 1;
+                                self.hdma5 = 0x80 | (blocks_left as u8);
+                                self.hdma = None;
+                            }
+                            _ => {
+                                self.hdma_src &= 0xFFF0;
+                                self.hdma_dst = 0x8000 | (self.hdma_dst & 0x1FF0);
+                                self.hdma = Some(Box::new(Hdma {
+                                    remaining: u16::from(length_bits + 1) * 0x10,
+                                    hblank_dma,
+                                    // Started during HBlank or with the LCD off, the first block
+                                    // is transferred right away
+                                    block_pending: hblank_dma
+                                        && (!self.ppu.lcd_enabled() || self.ppu.in_hblank()),
+                                }));
+                            }
+                        }
                     }
                     HWR_BCPS                => { self.clock(); self.ppu.write_bcps(data); },
                     HWR_BCPD                => { self.clock(); self.ppu.write_bcpd(data); },
@@ -500,6 +645,29 @@ impl SOC {
     }
 
     pub fn clock(&mut self) {
+        let div_stopped = match self.stop_mode {
+            StopMode::None => false,
+            StopMode::Stopped => {
+                self.clock_stopped();
+                return;
+            }
+            StopMode::SpeedSwitch(cycles) => {
+                self.stop_mode = if cycles > 1 {
+                    StopMode::SpeedSwitch(cycles - 1)
+                } else {
+                    StopMode::None
+                };
+                true
+            }
+        };
+
+        if let Some(address) = self.oam_bug_inc_dec.take() {
+            // Internal cycle, the inc/dec unit alone corrupts like a write
+            if (0xFE00..=0xFEFF).contains(&address) {
+                self.ppu.oam_bug(OamBugAccess::Write);
+            }
+        }
+
         let cycle = self.cycles;
         let double_speed = self.cpu_speed;
 
@@ -512,11 +680,34 @@ impl SOC {
         };
 
         SOC::clock_4_mhz(double_speed, cycle, || self.ppu.clock(&mut ctx));
-        self.timer.clock(&mut ctx);
+        if !div_stopped {
+            self.timer.clock(&mut ctx);
+        }
 
         SOC::clock_4_mhz(double_speed, cycle, || self.mbc.clock());
         SOC::clock_4_mhz(double_speed, cycle, || self.apu.clock());
-        self.serial.clock(&mut ctx);
+        self.serial
+            .clock(self.timer.get_counter(), double_speed, &mut ctx);
+
+        self.cycles += 1;
+    }
+
+    // Only the joypad is active in STOP mode
+    fn clock_stopped(&mut self) {
+        if self.joypad_input_low() {
+            self.stop_mode = StopMode::None;
+        }
+
+        let mut ctx = ClockContext {
+            interrupts: &mut self.r#if,
+            events: &mut self.event_bits,
+        };
+
+        SOC::clock_4_mhz(self.cpu_speed, self.cycles, || self.stop_cycles += 1);
+        if self.stop_cycles >= CYCLES_PER_FRAME {
+            self.stop_cycles = 0;
+            self.ppu.stopped_frame(&mut ctx);
+        }
 
         self.cycles += 1;
     }
@@ -542,7 +733,8 @@ impl SOC {
 
         SOC::clock_4_mhz(double_speed, cycle, || self.mbc.clock());
         SOC::clock_4_mhz(double_speed, cycle, || self.apu.clock());
-        self.serial.clock(&mut ctx);
+        self.serial
+            .clock(self.timer.get_counter(), double_speed, &mut ctx);
 
         self.cycles += 1;
     }
@@ -568,6 +760,40 @@ impl SOC {
         self.ppu.get_framebuffer()
     }
 
+    pub fn get_ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn set_ppu_debug_flags(&mut self, debug_flags: ppu::PpuDebugFlags) {
+        self.ppu.set_debug_flags(debug_flags);
+    }
+
+    // CGB boot ROMs leave the cartridge header at 0x100-0x1FF visible
+    pub fn boot_rom_byte(&self, address: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
+
+        match address {
+            0x0000..=0x00FF | 0x0200..=0x08FF => boot_rom.get(usize::from(address)).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn get_serial(&self) -> &serial::Serial {
+        &self.serial
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.cpu_speed
+    }
+
+    pub fn get_apu(&self) -> &apu::APU {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut apu::APU {
+        &mut self.apu
+    }
+
     pub fn close(&mut self) {
         if self.enable_saving {
             self.save();
@@ -605,6 +831,63 @@ impl SOC {
         }
     }
 
+    pub fn command_update(&mut self) {
+        let commands: Vec<EmulatorCommand> = match &self.command_recv {
+            Some(command_recv) => command_recv.try_iter().collect(),
+            None => return,
+        };
+
+        for command in commands {
+            match command {
+                EmulatorCommand::EnablePpuViewer(enable) => self.viewer_enabled = enable,
+                EmulatorCommand::SetPpuDebugFlags(debug_flags) => {
+                    self.set_ppu_debug_flags(debug_flags)
+                }
+                EmulatorCommand::SetChannelMuted(channel, muted) => {
+                    self.apu.set_channel_muted(channel, muted)
+                }
+                EmulatorCommand::SetChannelSoloed(channel, soloed) => {
+                    self.apu.set_channel_soloed(channel, soloed)
+                }
+                EmulatorCommand::StartRecording(path) => {
+                    if let Err(err) = self.apu.start_recording(&path) {
+                        eprintln!("Unable to record audio to {}: {}", path.display(), err);
+                    }
+                }
+                EmulatorCommand::StopRecording => {
+                    if let Err(err) = self.apu.stop_recording() {
+                        eprintln!("Unable to finish audio recording: {}", err);
+                    }
+                }
+                EmulatorCommand::StartVgmLog(path) => {
+                    if let Err(err) = self.apu.start_vgm_log(&path) {
+                        eprintln!("Unable to log audio to {}: {}", path.display(), err);
+                    }
+                }
+                EmulatorCommand::StopVgmLog => {
+                    if let Err(err) = self.apu.stop_vgm_log() {
+                        eprintln!("Unable to finish VGM log: {}", err);
+                    }
+                }
+                EmulatorCommand::StartChannelCapture(channel, path) => {
+                    if let Err(err) = self.apu.start_channel_capture_file(channel, &path) {
+                        eprintln!(
+                            "Unable to capture {:?} to {}: {}",
+                            channel,
+                            path.display(),
+                            err
+                        );
+                    }
+                }
+                EmulatorCommand::StopChannelCapture(channel) => {
+                    if let Err(err) = self.apu.stop_channel_capture(channel) {
+                        eprintln!("Unable to finish {:?} capture: {}", channel, err);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn process_events(&mut self) -> bool {
         if self.event_bits & SocEventBits::SocEventVSync as u8 == 0 {
             self.event_bits = 0;
@@ -620,6 +903,16 @@ impl SOC {
 
         self.input_update();
 
+        self.command_update();
+
+        if let Some(sgb) = &mut self.sgb {
+            sgb.vsync(&mut self.ppu);
+        }
+
+        if let (true, Some(viewer_chan)) = (self.viewer_enabled, &self.viewer_chan) {
+            _ = viewer_chan.try_send(Box::new(self.ppu.snapshot()));
+        }
+
         if self.enable_saving && self.last_saved_at.elapsed() > time::Duration::from_secs(60) {
             self.save();
             self.last_saved_at = time::Instant::now();
@@ -641,13 +934,64 @@ impl SOC {
         }
     }
 
-    fn clock_dma(&mut self) {
-        // @todo - When the CPU attempts to read a byte from ROM/RAM during a DMA transfer,
-        // instead of the actual value at the given memory address,
-        // the byte that is currently being transferred by the DMA transfer is returned.
-        // This also affects the CPU when fetching opcodes, allowing for code execution through DMA transfers.
-        // https://hacktix.github.io/GBEDG/dma/
+    // Called by the CPU when a 16-bit register is incremented/decremented in the next M-cycle
+    pub fn oam_bug_inc_dec(&mut self, address: u16) {
+        if self.ctx.comp_mode == CompatibilityMode::ModeDmg {
+            self.oam_bug_inc_dec = Some(address);
+        }
+    }
+
+    fn oam_bug(&mut self, address: u16, access: OamBugAccess) {
+        let inc_dec = self.oam_bug_inc_dec.take();
+
+        if self.ctx.comp_mode != CompatibilityMode::ModeDmg {
+            return;
+        }
+
+        let in_oam = |address: u16| (0xFE00..=0xFEFF).contains(&address);
+
+        let access = match (in_oam(address), inc_dec.is_some_and(in_oam)) {
+            (true, true) if access == OamBugAccess::Read => OamBugAccess::ReadIncDec,
+            (true, _) => access,
+            (false, true) => OamBugAccess::Write,
+            (false, false) => return,
+        };
 
+        self.ppu.oam_bug(access);
+    }
+
+    fn memory_bus(&self, address: u16) -> Option<MemoryBus> {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => Some(MemoryBus::External),
+            0x8000..=0x9FFF => Some(MemoryBus::Video),
+            0xC000..=0xFDFF if self.ctx.comp_mode.is_cgb_hardware() => Some(MemoryBus::Wram),
+            0xC000..=0xFDFF => Some(MemoryBus::External),
+            _ => None,
+        }
+    }
+
+    // When the CPU accesses the bus OAM DMA is reading from, instead of the actual value at the
+    // given memory address the byte that is currently being transferred is returned.
+    // This also affects opcode fetches, allowing for code execution through DMA transfers.
+    // https://hacktix.github.io/GBEDG/dma/
+    fn dma_bus_conflict(&self, address: u16) -> bool {
+        match &self.active_dma {
+            Some(active_dma) => {
+                // Sources above E000 read from WRAM
+                let src = if active_dma.src >= 0xE000 {
+                    0xC000
+                } else {
+                    active_dma.src
+                };
+
+                let bus = self.memory_bus(address);
+                bus.is_some() && bus == self.memory_bus(src)
+            }
+            None => false,
+        }
+    }
+
+    fn clock_dma(&mut self) {
         if let Some(active_dma) = &mut self.active_dma {
             let c = u16::from(active_dma.count);
 
@@ -662,6 +1006,7 @@ impl SOC {
             let byte = dma_read!(self, address);
 
             self.ppu.oam_dma(0xFE00 + c, byte);
+            self.dma_bus_value = byte;
 
             active_dma.cycles += 1;
             active_dma.count += 1;
@@ -682,43 +1027,79 @@ impl SOC {
     }
 
     fn clock_hdma(&mut self) {
-        // @todo - HDMA transfer is currently quickly hacked together to move memory either
-        // instantly in case of General-Purpose DMA or 16 bytes at a time at the start of HBlank for
-        // HBlank DMA. This doesn't respect actual timings that should halt the cpu and transfer only a few bytes
-        // per every M-cycle. Transfer timings: https://gbdev.io/pandocs/CGB_Registers.html#transfer-timings
         if let Some(active_hdma) = &mut self.hdma {
-            if active_hdma.hblank_dma {
-                if self.ppu.get_hblank_cycle() && !self.cpu_halted {
-                    let bytes_to_transfer: u16 =
-                        std::cmp::min(0x10, active_hdma.initial - active_hdma.count);
+            if active_hdma.hblank_dma && self.ppu.get_hblank_cycle() {
+                active_hdma.block_pending = true;
+            }
+        }
+    }
 
-                    for offset in active_hdma.count..active_hdma.count + bytes_to_transfer {
-                        let src_addr = active_hdma.src + offset;
-                        let dst_addr = active_hdma.dst + offset;
+    // Runs a due HDMA transfer between CPU instructions. The CPU is stalled for the duration
+    // of the transfer, returns the number of stalled cycles.
+    // Transfer timings: https://gbdev.io/pandocs/CGB_Registers.html#transfer-timings
+    pub fn clock_hdma_transfer(&mut self) -> u64 {
+        let bytes = match &mut self.hdma {
+            Some(active_hdma) if !active_hdma.hblank_dma => active_hdma.remaining,
+            Some(active_hdma) if active_hdma.block_pending => {
+                if self.ppu.lcd_enabled() && !self.ppu.in_hblank() {
+                    // HBlank ended while the CPU was halted, wait for the next one
+                    active_hdma.block_pending = false;
+                    return 0;
+                }
+                if self.cpu_halted {
+                    return 0;
+                }
+                0x10
+            }
+            _ => return 0,
+        };
 
-                        let byte = dma_read!(self, src_addr);
+        let start_cycles = self.cycles;
 
-                        self.ppu.write_vram(dst_addr, byte);
-                    }
+        // 0x10 bytes take 8 µs in either speed mode
+        let bytes_per_cycle = if self.cpu_speed { 1 } else { 2 };
+        let mut transferred = 0;
+        let mut end_of_vram = false;
 
-                    active_hdma.count += bytes_to_transfer;
+        while transferred < bytes && !end_of_vram {
+            for _ in 0..bytes_per_cycle {
+                let byte = self.hdma_read(self.hdma_src);
+                self.ppu.write_vram(self.hdma_dst, byte);
 
-                    if active_hdma.initial - active_hdma.count == 0 {
-                        self.hdma = None;
-                    }
-                }
-            } else {
-                for offset in 0..active_hdma.initial {
-                    let src_addr = active_hdma.src + offset;
-                    let dst_addr = active_hdma.dst + offset;
+                self.hdma_src = self.hdma_src.wrapping_add(1);
+                self.hdma_dst += 1;
+            }
 
-                    let byte = dma_read!(self, src_addr);
+            transferred += bytes_per_cycle;
+            // The transfer stops when the destination passes the end of VRAM
+            end_of_vram = self.hdma_dst > 0x9FFF;
 
-                    self.ppu.write_vram(dst_addr, byte);
-                }
+            self.clock();
+        }
 
+        if let Some(active_hdma) = &mut self.hdma {
+            active_hdma.remaining -= transferred;
+            active_hdma.block_pending = false;
+
+            if active_hdma.remaining == 0 || end_of_vram {
                 self.hdma = None;
+                self.hdma5 = 0xFF;
+            }
+        }
+
+        self.cycles - start_cycles
+    }
+
+    fn hdma_read(&self, address: u16) -> u8 {
+        match address {
+            // VRAM can't be used as source
+            0x8000..=0x9FFF => 0xFF,
+            // Mirrors cartridge RAM
+            0xE000..=0xFFFF => {
+                let address = address - 0x4000;
+                dma_read!(self, address)
             }
+            _ => dma_read!(self, address),
         }
     }
 
@@ -730,7 +1111,27 @@ impl SOC {
         self.cpu_halted = halted;
     }
 
-    pub fn cgb_speed_switch(&mut self) {
+    pub fn is_stopped(&self) -> bool {
+        self.stop_mode != StopMode::None
+    }
+
+    // Any selected P1 input line is low
+    pub fn joypad_input_low(&self) -> bool {
+        util::calc_button_bits(&self.buttons, !self.p1_select_buttons, !self.p1_select_dpad) != 0xF
+    }
+
+    pub fn speed_switch_armed(&self) -> bool {
+        self.cpu_speed_armed
+    }
+
+    pub fn stop(&mut self) {
+        self.timer.reset_div();
+        self.stop_mode = StopMode::Stopped;
+        self.stop_cycles = 0;
+    }
+
+    // pause: the CPU waits for the switch to complete, unless an interrupt was pending
+    pub fn cgb_speed_switch(&mut self, pause: bool) {
         if !self.cpu_speed_armed {
             return;
         }
@@ -739,5 +1140,9 @@ impl SOC {
 
         self.cpu_speed_armed = false;
         self.cpu_speed = !self.cpu_speed;
+
+        if pause {
+            self.stop_mode = StopMode::SpeedSwitch(CYCLES_SPEED_SWITCH);
+        }
     }
 }
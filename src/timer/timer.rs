@@ -13,12 +13,12 @@ pub struct Timer {
 }
 
 impl Timer {
-    pub fn new() -> Timer {
+    pub fn new(div: u16) -> Timer {
         Self {
             and_result: false,
             tima_overflow: false,
             tac: 0,
-            div: 0xABCC,
+            div,
             tima: 0,
             tma: 0,
         }
@@ -70,6 +70,11 @@ impl Timer {
         (self.div >> 8) as u8
     }
 
+    // Internal system counter, also clocks the serial port
+    pub fn get_counter(&self) -> u16 {
+        self.div
+    }
+
     pub fn read_tima(&self) -> u8 {
         self.tima
     }
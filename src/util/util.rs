@@ -22,6 +22,20 @@ pub fn value(high: u8, low: u8) -> u16 {
     (u16::from(high) << 8) | u16::from(low)
 }
 
+pub fn rgb_from_gb_color(gb_color: u16) -> (u8, u8, u8) {
+    let red_intensity = gb_color & 0x1F;
+    let green_intensity = (gb_color >> 5) & 0x1F;
+    let blue_intensity = (gb_color >> 10) & 0x1F;
+
+    const INTENSITY: f64 = 8.22580;
+
+    (
+        (INTENSITY * f64::from(red_intensity as u8)) as u8,
+        (INTENSITY * f64::from(green_intensity as u8)) as u8,
+        (INTENSITY * f64::from(blue_intensity as u8)) as u8,
+    )
+}
+
 pub fn calc_button_bits(
     buttons: &[bool; GbButton::GbButtonMax as usize],
     select_buttons: bool,